@@ -1,8 +1,11 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use genai::chat::{ChatMessage, ChatRequest, ContentPart};
-use genai::Client;
-use tauri::Manager;
+use genai::chat::{ChatMessage, ChatOptions, ChatRequest, ChatResponseFormat, ContentPart};
+use genai::resolver::Endpoint;
+use genai::{Client, ServiceTarget};
+use tauri::{Emitter, Manager};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutEvent, ShortcutState};
 use reqwest::Client as HttpClient;
 use serde_json::json;
 
@@ -16,10 +19,11 @@ use std::sync::Arc;
 use uuid::Uuid;
 
 use tauri::{LogicalPosition, LogicalSize, PhysicalPosition, Position, Size};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Mutex;
-use std::time::{Duration, Instant};
-use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 
 #[cfg(windows)]
 use winreg::{enums::HKEY_CURRENT_USER, RegKey};
@@ -34,21 +38,709 @@ struct ToggleState {
     visible: AtomicBool,
     last_toggle: Mutex<Instant>,
     last_nudge: Mutex<Instant>,
+    stealth_profile: Mutex<String>,
+    max_size: Mutex<Option<(f64, f64)>>, // Logical bounds no resize may exceed
+    last_position_op: Mutex<(String, Instant)>, // Held while moving/resizing so inputs apply in order
+    capture_shortcut: Mutex<Option<String>>, // Accelerator bound to capture_and_queue in Rust
+    confine_to_monitor: AtomicBool, // Keep moves and nudges inside the monitor the overlay is on
+    focus_on_show: AtomicBool, // Activate the overlay when it becomes visible; off leaves focus with the current app
+    peek: AtomicBool, // Shrunk to the peek-mode status strip
+}
+
+// Positioning requests identical to the previous one within this window are dropped
+const POSITION_COALESCE_MS: u64 = 60;
+
+// Take the positioning lock for one move/nudge/resize; None means the request duplicates the last one
+fn begin_position_op<'a>(state: &'a ToggleState, op: String) -> Option<std::sync::MutexGuard<'a, (String, Instant)>> {
+    let mut last = state.last_position_op.lock().unwrap();
+    let now = Instant::now();
+    if last.0 == op && now.duration_since(last.1) < Duration::from_millis(POSITION_COALESCE_MS) {
+        return None;
+    }
+    *last = (op, now);
+    Some(last)
+}
+
+// Take the positioning lock without coalescing, for relative moves where every request must apply
+fn lock_position_op(state: &ToggleState, op: String) -> std::sync::MutexGuard<'_, (String, Instant)> {
+    let mut last = state.last_position_op.lock().unwrap();
+    *last = (op, Instant::now());
+    last
+}
+
+// Coherent bundle of window properties; content protection also drives display affinity on Windows
+struct StealthFlags {
+    always_on_top: bool,
+    decorations: bool,
+    content_protected: bool,
+    skip_taskbar: bool,
+    ignore_cursor: bool,
+}
+
+fn stealth_flags(profile: &str) -> Option<StealthFlags> {
+    match profile {
+        // Hidden from capture and click-through: nothing on screen reacts to the overlay
+        "max" => Some(StealthFlags {
+            always_on_top: true,
+            decorations: false,
+            content_protected: true,
+            skip_taskbar: true,
+            ignore_cursor: true,
+        }),
+        // Hidden from capture but usable with the mouse (startup configuration)
+        "interactive" => Some(StealthFlags {
+            always_on_top: true,
+            decorations: false,
+            content_protected: true,
+            skip_taskbar: true,
+            ignore_cursor: false,
+        }),
+        // Ordinary window, visible in screen shares, for demos and recordings
+        "visible-demo" => Some(StealthFlags {
+            always_on_top: false,
+            decorations: true,
+            content_protected: false,
+            skip_taskbar: false,
+            ignore_cursor: false,
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct ConversationTurn {
+    role: String, // "user" or "assistant"
+    text: String,
+}
+
+// The last single-shot request, kept so it can be replayed against another model
+#[derive(Clone)]
+struct RecordedRequest {
+    mode: String, // "text", "image" or "queue"
+    system: String,
+    prompt: String,
+    image_paths: Vec<String>,
+    model: String,
+    answer: String,
+}
+
+struct Conversation {
+    turns: Mutex<Vec<ConversationTurn>>,
+    dedupe: AtomicBool, // Collapse repeated identical answers as they are appended
+    last_request: Mutex<Option<RecordedRequest>>,
+    retired_images: Mutex<HashSet<String>>, // Auto-cleared captures kept on disk while last_request still replays them
+    last_response_meta: Mutex<Option<ResponseMeta>>,
+    last_fingerprint: Mutex<Option<(Vec<String>, String)>>, // (image paths, fingerprint) from fingerprint_capture
+    session_id: String, // Groups history entries saved during this run
+}
+
+// Registry of long-running tokio tasks (timers, watchers, servers) so they can be stopped together
+struct BackgroundTasks {
+    tasks: Mutex<HashMap<String, tauri::async_runtime::JoinHandle<()>>>,
+}
+
+impl BackgroundTasks {
+    // Track a task under a name, aborting any previous task registered with the same name
+    fn track(&self, name: &str, handle: tauri::async_runtime::JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if let Some(previous) = tasks.insert(name.to_string(), handle) {
+            previous.abort();
+        }
+    }
+
+    fn stop(&self, name: &str) -> bool {
+        match self.tasks.lock().unwrap().remove(name) {
+            Some(handle) => {
+                let running = !handle.inner().is_finished();
+                handle.abort();
+                running
+            }
+            None => false,
+        }
+    }
 }
 
 struct ImageQueue {
     images: Mutex<VecDeque<String>>,
     last_capture: Mutex<Instant>,
+    captions: Mutex<HashMap<String, String>>, // Optional label sent ahead of a queued image
+    window_titles: Mutex<HashMap<String, String>>, // Foreground window title at capture time
+    groups: Mutex<HashMap<String, Vec<String>>>, // Named sets of queued image paths
+    pinned: Mutex<HashSet<String>>, // Paths kept (file included) across clears
+    auto_clear: AtomicBool, // Drop sent images (and their files) after a successful queue send
+    single_slot: AtomicBool, // "single" queue mode: each capture replaces the previous one
+    last_activity: Mutex<Instant>, // Last capture or send, for the idle auto-clear
+    session_image: Mutex<Option<String>>, // Growing stitched capture of a scrolled page, queued once
+    last_screen: Mutex<Option<image::RgbaImage>>, // Previous full frame seen by capture_new_content
 }
 
 struct AppConfig {
     api_key: Mutex<Option<String>>, // Stored for reference; env var is also set
     model: Mutex<String>,
     hf_token: Mutex<Option<String>>, // Hugging Face token for GPT-OSS-120B
+    ocr_cleanup: AtomicBool, // Opt-in 0/O, 1/l repair for OCR'd code before sending
+    gemini_endpoint: Mutex<Option<String>>, // Custom base URL (proxy); None uses genai's default
+    endpoint_allowlist: Mutex<Vec<String>>, // Hosts trusted with the Gemini key
+    allow_custom_endpoint: AtomicBool, // Explicit opt-in for endpoints outside the allowlist
+    capture_enhance: Mutex<Option<f32>>, // Contrast normalization strength (0.0-1.0) applied to captures
+    persisted: Mutex<PersistedConfig>, // Settings saved to config.json across restarts
+    response_locale: Mutex<Option<String>>, // None answers in English
+    answer_max_words: Mutex<Option<u32>>, // Word budget for prose in answers; None is unbounded
+    video_call_guard: AtomicBool, // Refuse captures while a meeting window is on screen
+    video_call_confirmed: AtomicBool, // One-shot override set after the user confirms
+    meeting_lockdown: AtomicBool, // Refuse AI calls while a meeting window is in the foreground
+    meeting_lockdown_override: AtomicBool, // One-shot: lets the next AI call through
+    image_pool: Mutex<Arc<rayon::ThreadPool>>, // Bounded workers for encoding/processing images
+    hide_windows_on_capture: AtomicBool, // Hide every app window (overlay, dimmer) while capturing
+    last_hidden_windows: Mutex<Vec<String>>, // Labels hidden by the most recent capture
+    image_server_port: Mutex<Option<u16>>, // Set while the local capture server is running
+    request_slots: Mutex<(usize, Arc<tokio::sync::Semaphore>)>, // (limit, permits) gating concurrent AI calls
+    max_capture_dimension: Mutex<Option<u32>>, // Longest side a saved capture may have; None keeps full size
+    strip_watermark: AtomicBool, // Opt-in removal of faint proctoring overlays from captures
+    hf_anonymize: AtomicBool, // Scrub PII from extracted text before it reaches Hugging Face
+    platform_hint: Mutex<Option<&'static str>>, // Coding platform on screen, selects the I/O convention
+    last_hf_raw: Mutex<Option<HfRawResponse>>, // Unparsed reply from the last Beast Mode HF call
+    last_beast: Mutex<Option<(String, String)>>, // (extraction, answer) of the last answered Beast Mode run
+    ocr_min_confidence: Mutex<Option<f32>>, // Hybrid sends: OCR text at or above this confidence replaces the image
+    image_routes: Mutex<HashMap<String, ImageRoute>>, // Whether each image was last sent as image or text
+    hf_auto_retry: AtomicBool, // Wait out one HF cold start (503 with estimated_time) and retry
+    blank_frame_retries: AtomicU32, // Re-grab all-black captures this many times before failing
+    ocr_preserve_indent: AtomicBool, // Rebuild code indentation from word positions in OCR text
+    adaptive_threshold: Mutex<Option<u32>>, // Adaptive model selection: token size at which to escalate
+    model_explicit: AtomicBool, // A model was chosen (set_model, a profile, GEMINI_MODEL), so adaptive selection stands aside
+    recording: Mutex<Option<Recording>>, // Active session recording, see start_recording
+    ocr_languages: Mutex<Vec<String>>, // Tesseract packs joined with "+"; empty uses Tesseract's default
+    stream_flush_ms: AtomicU32, // Coalescing window for streamed answer chunks
+    config_repairs: Mutex<Vec<String>>, // Config fields reset at startup, until verify_config reports them
+    render_font_size: Mutex<f32>, // Pixels, for answers rendered to images
+    screen_context: Mutex<Option<ScreenContext>>, // OCR'd region sent with the system prompt, see capture_area_as_context
+    app: tauri::AppHandle, // For saving settings and emitting events from helpers that only see AppConfig
+    images_first: AtomicBool, // Put image parts ahead of the prompt text in image and queue requests
+    auto_continue: AtomicU32, // Most "continue" follow-ups for an answer that looks truncated; 0 is off
+}
+
+// Today's generation requests and tokens, persisted so a restart mid-day keeps counting
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct QuotaUsage {
+    day: u64, // quota_day() the counts belong to
+    requests: u32,
+    tokens: u64,
+    exhausted: bool, // The API answered RESOURCE_EXHAUSTED / 429 today
+    warned: bool, // "quota-warning" already sent today
+}
+
+#[derive(Clone, serde::Serialize)]
+struct HfRawResponse {
+    status: Option<u16>, // None when the request never got a response
+    body: Option<String>,
+    error: Option<String>, // Transport or body-read error
+    received_at: u64,
+}
+
+fn locale_language(locale: &str) -> String {
+    let code = locale.split(['-', '_']).next().unwrap_or("").to_lowercase();
+    let language = match code.as_str() {
+        "ar" => "Arabic",
+        "bn" => "Bengali",
+        "de" => "German",
+        "en" => "English",
+        "es" => "Spanish",
+        "fr" => "French",
+        "hi" => "Hindi",
+        "id" => "Indonesian",
+        "it" => "Italian",
+        "ja" => "Japanese",
+        "ko" => "Korean",
+        "nl" => "Dutch",
+        "pl" => "Polish",
+        "pt" => "Portuguese",
+        "ru" => "Russian",
+        "ta" => "Tamil",
+        "te" => "Telugu",
+        "tr" => "Turkish",
+        "uk" => "Ukrainian",
+        "vi" => "Vietnamese",
+        "zh" => "Chinese",
+        _ => return format!("the language of locale '{}'", locale),
+    };
+    language.to_string()
+}
+
+// Compose the system prompt for a call from its base instruction plus user preferences
+fn system_prompt(cfg: &AppConfig, model: &str, base: &str) -> String {
+    // A prompt tuned for the called model's provider is added after the call's own instruction
+    let mut prompt = base.to_string();
+    if let Some(extra) = cfg.persisted.lock().unwrap().provider_system_prompts.get(&model_provider(model)) {
+        prompt.push_str(&format!("\n\n{}", extra));
+    }
+    if let Some(locale) = cfg.response_locale.lock().unwrap().as_deref() {
+        prompt.push_str(&format!(" Respond in {}; keep code, identifiers, and keywords as-is.", locale_language(locale)));
+    }
+    if let Some(max_words) = *cfg.answer_max_words.lock().unwrap() {
+        prompt.push_str(&format!(" Keep the explanation under {} words; code blocks do not count toward this limit.", max_words));
+    }
+    if let Some(convention) = cfg.platform_hint.lock().unwrap().and_then(platform_io_convention) {
+        prompt.push_str(&format!(" {}", convention));
+    }
+    if let Some(context) = cfg.screen_context.lock().unwrap().as_ref() {
+        // Screen text bound for Hugging Face gets the same PII scrub as the extracted content
+        let text = if model == BEAST_HF_MODEL && cfg.hf_anonymize.load(Ordering::SeqCst) {
+            anonymize_text(&context.text).0
+        } else {
+            context.text.clone()
+        };
+        prompt.push_str(&format!(
+            "\n\nStanding context captured from the screen (constraints that apply to every question; the user's message holds the specific question):\n{}",
+            text
+        ));
+    }
+    if let Some(template) = cfg.persisted.lock().unwrap().answer_template.as_deref() {
+        prompt.push_str(&format!(
+            "\n\nFormat every answer with this template. Replace each {{{{placeholder}}}} with the matching content and keep all other template text verbatim:\n{}",
+            template
+        ));
+    }
+    prompt
+}
+
+// genai adapter name ("gemini", "ollama", ...) that serves a model; router names like "openai/gpt-oss-120b" use the part after the slash
+fn model_provider(model: &str) -> String {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    genai::adapter::AdapterKind::from_model(model)
+        .map(|kind| kind.as_lower_str().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+// Fixed template lines (those without a {{placeholder}}) that an answer is missing
+fn missing_template_lines(template: &str, answer: &str) -> Vec<String> {
+    template
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.contains("{{"))
+        .filter(|line| !answer.lines().any(|answer_line| answer_line.trim() == *line))
+        .map(String::from)
+        .collect()
+}
+
+// Settings that survive restarts; missing fields fall back to defaults when loading
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct PersistedConfig {
+    hf_headers: HashMap<String, String>, // Extra headers merged into Beast Mode HF requests
+    named_regions: HashMap<String, NamedRegion>,
+    macros: HashMap<String, Vec<MacroStep>>,
+    profiles: HashMap<String, ProviderProfile>, // Keys live in the OS keychain, not here
+    active_profile: Option<String>,
+    answer_template: Option<String>, // Structure with {{placeholders}} every answer should follow
+    provider_system_prompts: HashMap<String, String>, // Keyed by provider, e.g. "gemini" or "ollama"
+    cert_pins: HashMap<String, Vec<String>>, // Host (subdomains included) -> accepted public key (SPKI) SHA-256, hex
+    temp_policy: TempPolicy,
+    quota_limits: QuotaLimits,
+    quota_usage: QuotaUsage,
+    peek_restore_size: Option<(f64, f64)>, // Logical size the overlay returns to when peek mode ends
+    peeking: bool, // Peek mode was on at exit; startup shrinks the overlay again
+}
+
+// Daily allowances to warn against; Gemini doesn't report remaining quota, so these come from the user's plan
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct QuotaLimits {
+    requests_per_day: Option<u32>,
+    tokens_per_day: Option<u64>,
+}
+
+// Where captures and other generated images go under the system temp dir, and how long they stay
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct TempPolicy {
+    prefix: String, // Subdirectory name
+    max_age_seconds: Option<u64>,
+    max_total_bytes: Option<u64>,
+}
+
+impl Default for TempPolicy {
+    fn default() -> Self {
+        TempPolicy { prefix: "tauri_gemini".to_string(), max_age_seconds: None, max_total_bytes: None }
+    }
+}
+
+// Snapshot of the backend settings; the provider is derived from the model name
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct ProviderProfile {
+    provider: String,
+    model: String,
+    endpoint: Option<String>,
+    allow_custom_endpoint: bool,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct NamedRegion {
+    x: i32, // Relative to the region's monitor
+    y: i32,
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    monitor: Option<MonitorKey>, // None for regions saved before monitors were tracked (first screen)
+}
+
+// Properties that identify a monitor across reconfiguration, when its index may have changed
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct MonitorKey {
+    id: u32,
+    width: u32,
+    height: u32,
+    scale_factor: f32,
+}
+
+impl MonitorKey {
+    fn of(screen: &Screen) -> Self {
+        let info = &screen.display_info;
+        MonitorKey { id: info.id, width: info.width, height: info.height, scale_factor: info.scale_factor }
+    }
+}
+
+// Find the region's monitor: same id and resolution, else same resolution and scale.
+// Falls back to the primary display, returning a warning when the original monitor is gone.
+fn find_region_monitor<'a>(screens: &'a [Screen], key: Option<&MonitorKey>) -> Result<(&'a Screen, Option<String>), String> {
+    let Some(key) = key else {
+        return Ok((screens.first().ok_or("No screens found")?, None));
+    };
+    let primary = screens
+        .iter()
+        .find(|screen| screen.display_info.is_primary)
+        .or(screens.first())
+        .ok_or("No screens found")?;
+    let same_shape = |screen: &&Screen| {
+        let info = &screen.display_info;
+        (info.width, info.height) == (key.width, key.height)
+    };
+    let found = screens
+        .iter()
+        .filter(same_shape)
+        .find(|screen| screen.display_info.id == key.id)
+        .or_else(|| screens.iter().filter(same_shape).find(|screen| screen.display_info.scale_factor == key.scale_factor));
+    Ok(match found {
+        Some(screen) => (screen, None),
+        None => (
+            primary,
+            Some(format!("Monitor {} ({}x{}) is no longer connected; captured from the primary display", key.id, key.width, key.height)),
+        ),
+    })
+}
+
+// One step of a user macro, mirroring an existing command and its arguments
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum MacroStep {
+    CaptureNamedRegion { name: String },
+    SetModel { model: String },
+    CallGeminiSmart { prompt: String },
+}
+
+fn config_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join("config.json"))
+}
+
+// Load config.json, keeping every field that still parses; returns the config and the fields reset to
+// defaults. A repaired file is rewritten, with the damaged original kept as config.json.corrupt.
+fn load_persisted_config(app: &tauri::AppHandle) -> (PersistedConfig, Vec<String>) {
+    let Ok(path) = config_path(app) else {
+        return (PersistedConfig::default(), Vec::new());
+    };
+    let raw = match fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (PersistedConfig::default(), Vec::new()),
+        Err(e) => return (PersistedConfig::default(), vec![format!("config.json could not be read ({}); using defaults", e)]),
+    };
+    let (config, reset) = repair_config(&raw);
+    if !reset.is_empty() {
+        let _ = fs::copy(&path, path.with_extension("json.corrupt"));
+        let _ = save_persisted_config(app, &config);
+    }
+    (config, reset)
+}
+
+// Parse a config file field by field: fields that fail to deserialize fall back to their defaults
+fn repair_config(raw: &str) -> (PersistedConfig, Vec<String>) {
+    if let Ok(config) = serde_json::from_str::<PersistedConfig>(raw) {
+        return (config, Vec::new());
+    }
+    let Ok(serde_json::Value::Object(fields)) = serde_json::from_str::<serde_json::Value>(raw) else {
+        return (PersistedConfig::default(), vec!["config.json is not valid JSON; every setting was reset".to_string()]);
+    };
+    let mut kept = serde_json::Map::new();
+    let mut reset = Vec::new();
+    for (key, value) in fields {
+        let mut trial = kept.clone();
+        trial.insert(key.clone(), value);
+        if serde_json::from_value::<PersistedConfig>(serde_json::Value::Object(trial.clone())).is_ok() {
+            kept = trial;
+        } else {
+            reset.push(key);
+        }
+    }
+    let config = serde_json::from_value(serde_json::Value::Object(kept)).unwrap_or_default();
+    (config, reset)
+}
+
+// Write via a temporary file and a rename, so a crash mid-write never leaves a truncated file behind
+fn write_atomic(path: &std::path::Path, contents: &str) -> Result<(), String> {
+    use std::io::Write;
+
+    let temp = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp).map_err(|e| e.to_string())?;
+    file.write_all(contents.as_bytes()).and_then(|_| file.sync_all()).map_err(|e| e.to_string())?;
+    drop(file);
+    fs::rename(&temp, path).map_err(|e| e.to_string())
+}
+
+fn save_persisted_config(app: &tauri::AppHandle, config: &PersistedConfig) -> Result<(), String> {
+    let raw = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+    write_atomic(&config_path(app)?, &raw)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ConfigRepair {
+    reset: Vec<String>, // Fields (or whole-file problems) that fell back to defaults
+}
+
+// Re-check config.json now: damaged fields are reset (and the file rewritten) while valid ones are kept.
+// Repairs made at startup, before the UI could hear "config-repaired", are included.
+#[tauri::command]
+fn verify_config(app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<ConfigRepair, String> {
+    let mut reset = std::mem::take(&mut *cfg.config_repairs.lock().map_err(|_| "Lock poisoned")?);
+    let path = config_path(&app)?;
+    if let Ok(raw) = fs::read_to_string(&path) {
+        let (config, damaged) = repair_config(&raw);
+        if !damaged.is_empty() {
+            let _ = fs::copy(&path, path.with_extension("json.corrupt"));
+            save_persisted_config(&app, &config)?;
+            *cfg.persisted.lock().map_err(|_| "Lock poisoned")? = config;
+            let _ = app.emit("config-repaired", ConfigRepair { reset: damaged.clone() });
+            reset.extend(damaged);
+        }
+    }
+    Ok(ConfigRepair { reset })
+}
+
+fn endpoint_host_allowed(endpoint: &str, allowlist: &[String]) -> Result<bool, String> {
+    let url = reqwest::Url::parse(endpoint).map_err(|e| format!("Invalid endpoint URL: {}", e))?;
+    let host = url.host_str().ok_or("Endpoint URL has no host")?.to_lowercase();
+    Ok(allowlist.iter().any(|allowed| {
+        let allowed = allowed.trim().to_lowercase();
+        host == allowed || host.ends_with(&format!(".{}", allowed))
+    }))
+}
+
+// Custom Gemini endpoint, refusing one the key must not be sent to
+fn trusted_gemini_endpoint(cfg: &AppConfig) -> Result<Option<String>, String> {
+    let endpoint = cfg.gemini_endpoint.lock().map_err(|_| "Lock poisoned")?.clone();
+    let Some(endpoint) = endpoint else {
+        return Ok(None);
+    };
+
+    let allowlist = cfg.endpoint_allowlist.lock().map_err(|_| "Lock poisoned")?.clone();
+    if !endpoint_host_allowed(&endpoint, &allowlist)? && !cfg.allow_custom_endpoint.load(Ordering::SeqCst) {
+        return Err(format!(
+            "Refusing to send the Gemini API key to {}: host is not in the endpoint allowlist. Set allow_custom_endpoint to true to override.",
+            endpoint
+        ));
+    }
+    Ok(Some(endpoint))
+}
+
+// Normalize a SHA-256 hash as hex ("AB:CD:..." or plain) or as base64 ("pin-sha256" style) to plain hex
+fn normalize_fingerprint(fingerprint: &str) -> Result<String, String> {
+    let compact: String = fingerprint.chars().filter(|c| *c != ':' && !c.is_whitespace()).collect();
+    let hex = match general_purpose::STANDARD.decode(&compact) {
+        Ok(bytes) if bytes.len() == 32 && compact.len() == 44 => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        _ => compact.to_lowercase(),
+    };
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' is not a SHA-256 hash (64 hex digits or 44 base64 characters)", fingerprint));
+    }
+    Ok(hex)
+}
+
+// Pin mismatch seen by one client, so the call that hit it can report it instead of a bare TLS error
+type PinFailure = Arc<Mutex<Option<String>>>;
+
+// Accepts only chains that pass normal WebPKI validation and, for pinned hosts, where the public key
+// (SubjectPublicKeyInfo) hash of the leaf or an intermediate is one of the pins, so pins survive certificate renewals
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    inner: Arc<rustls::client::WebPkiServerVerifier>,
+    pins: HashMap<String, Vec<String>>,
+    failure: PinFailure,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        use sha2::Digest;
+
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let rustls::pki_types::ServerName::DnsName(name) = server_name else {
+            return Ok(verified);
+        };
+        let host = name.as_ref().to_lowercase();
+        let pinned = self
+            .pins
+            .iter()
+            .filter(|(pinned_host, _)| host == **pinned_host || host.ends_with(&format!(".{}", pinned_host)))
+            .flat_map(|(_, fingerprints)| fingerprints)
+            .collect::<Vec<_>>();
+        if pinned.is_empty() {
+            return Ok(verified);
+        }
+        // The chain passed WebPKI validation above; a pin on any key in it (leaf or intermediate) matches,
+        // so pinning an intermediate survives the provider's routine leaf key rotations
+        let presented = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| webpki::EndEntityCert::try_from(cert).ok())
+            .map(|cert| sha2::Sha256::digest(cert.subject_public_key_info().as_ref()).iter().map(|b| format!("{:02x}", b)).collect::<String>())
+            .collect::<Vec<_>>();
+        if presented.iter().any(|hash| pinned.contains(&hash)) {
+            return Ok(verified);
+        }
+        let message = format!(
+            "Certificate pin mismatch for {}: none of the chain's public key hashes ({}) is pinned. The connection may be intercepted.",
+            host,
+            presented.join(", ")
+        );
+        if let Ok(mut failure) = self.failure.lock() {
+            *failure = Some(message.clone());
+        }
+        Err(rustls::Error::General(message))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+// HTTP client builder for API calls; with certificate pins configured it uses rustls with the pin check,
+// recording a mismatch in `failure`
+fn http_client_builder(cfg: &AppConfig, failure: &PinFailure) -> Result<reqwest::ClientBuilder, String> {
+    let pins = cfg.persisted.lock().map_err(|_| "Lock poisoned")?.cert_pins.clone();
+    if pins.is_empty() {
+        return Ok(HttpClient::builder());
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let roots = rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let inner = rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| e.to_string())?;
+    let verifier = PinnedCertVerifier { inner, pins, failure: failure.clone() };
+    let tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Ok(HttpClient::builder().use_preconfigured_tls(tls))
+}
+
+// Error text for a failed API call, naming a certificate pin mismatch when that is what broke it
+fn api_error(failure: &PinFailure, error: impl std::fmt::Display) -> String {
+    match failure.lock().ok().and_then(|mut failure| failure.take()) {
+        Some(mismatch) => mismatch,
+        None => error.to_string(),
+    }
+}
+
+// Pin the accepted public key (SPKI) SHA-256 hashes of a leaf or intermediate certificate for a host and its subdomains
+// (e.g. "googleapis.com"); an empty list removes the host's pins. Returns every pin.
+#[tauri::command]
+fn set_cert_pins(
+    host: String,
+    fingerprints: Vec<String>,
+    app: tauri::AppHandle,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<HashMap<String, Vec<String>>, String> {
+    let host = host.trim().trim_start_matches("*.").to_lowercase();
+    if host.is_empty() || host.contains(['/', ':']) {
+        return Err(format!("'{}' is not a host name", host));
+    }
+    let fingerprints = fingerprints.iter().map(|f| normalize_fingerprint(f)).collect::<Result<Vec<_>, _>>()?;
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    if fingerprints.is_empty() {
+        persisted.cert_pins.remove(&host);
+    } else {
+        persisted.cert_pins.insert(host, fingerprints);
+    }
+    save_persisted_config(&app, &persisted)?;
+    Ok(persisted.cert_pins.clone())
+}
+
+// A genai client plus the pin mismatch slot its requests report to
+struct GeminiClient {
+    client: Client,
+    pin_failure: PinFailure,
+}
+
+impl std::ops::Deref for GeminiClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+// Build the Gemini client, honouring a trusted custom endpoint and any certificate pins
+fn gemini_client(cfg: &AppConfig) -> Result<GeminiClient, String> {
+    let pin_failure = PinFailure::default();
+    let pinned = !cfg.persisted.lock().map_err(|_| "Lock poisoned")?.cert_pins.is_empty();
+    let endpoint = trusted_gemini_endpoint(cfg)?;
+    if endpoint.is_none() && !pinned {
+        return Ok(GeminiClient { client: Client::default(), pin_failure });
+    }
+
+    let mut builder = Client::builder();
+    if pinned {
+        builder = builder.with_reqwest(http_client_builder(cfg, &pin_failure)?.build().map_err(|e| e.to_string())?);
+    }
+    if let Some(endpoint) = endpoint {
+        builder = builder.with_service_target_resolver_fn(move |mut target: ServiceTarget| {
+            target.endpoint = Endpoint::from_owned(endpoint);
+            Ok(target)
+        });
+    }
+    Ok(GeminiClient { client: builder.build(), pin_failure })
 }
 
 #[tauri::command]
-fn move_window(position: &str, app: tauri::AppHandle) {
+fn move_window(position: &str, state: tauri::State<ToggleState>, app: tauri::AppHandle) {
+    let Some(_op) = begin_position_op(&state, format!("move:{}", position)) else {
+        return;
+    };
     if let Some(window) = app.get_webview_window("main") {
         let screen = window.primary_monitor().unwrap().unwrap();
         let screen_size = screen.size();
@@ -70,12 +762,36 @@ fn move_window(position: &str, app: tauri::AppHandle) {
             ),
             _ => (100.0, 100.0),
         };
+        if state.confine_to_monitor.load(Ordering::SeqCst) {
+            let scale = window.scale_factor().unwrap_or(1.0);
+            let (x, y) = confine_to_current_monitor(&window, (x * scale).round() as i32, (y * scale).round() as i32);
+            let _ = window.set_position(Position::Physical(PhysicalPosition { x, y }));
+            return;
+        }
         window
             .set_position(Position::Logical(LogicalPosition { x, y }))
             .unwrap();
     }
 }
 
+// Clamp a physical position so the whole window stays on the monitor it currently occupies
+fn confine_to_current_monitor(window: &tauri::WebviewWindow, x: i32, y: i32) -> (i32, i32) {
+    let (Ok(Some(monitor)), Ok(size)) = (window.current_monitor(), window.outer_size()) else {
+        return (x, y);
+    };
+    let (origin, area) = (monitor.position(), monitor.size());
+    // A window larger than the monitor is pinned to its top-left corner
+    let max_x = (origin.x + area.width as i32 - size.width as i32).max(origin.x);
+    let max_y = (origin.y + area.height as i32 - size.height as i32).max(origin.y);
+    (x.clamp(origin.x, max_x), y.clamp(origin.y, max_y))
+}
+
+#[tauri::command]
+fn confine_to_monitor(enabled: bool, state: tauri::State<ToggleState>) -> bool {
+    state.confine_to_monitor.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
 #[tauri::command]
 fn nudge_window(state: tauri::State<ToggleState>, direction: &str, step: i32, app: tauri::AppHandle) {
     // Debounce arrow holds and duplicate firings: allow nudges every 120ms
@@ -87,6 +803,9 @@ fn nudge_window(state: tauri::State<ToggleState>, direction: &str, step: i32, ap
         }
         *last = now;
     }
+    let Some(_op) = begin_position_op(&state, format!("nudge:{}:{}", direction, step)) else {
+        return;
+    };
     if let Some(window) = app.get_webview_window("main") {
         if let Ok(current_pos) = window.outer_position() {
             let mut new_x = current_pos.x;
@@ -101,12 +820,66 @@ fn nudge_window(state: tauri::State<ToggleState>, direction: &str, step: i32, ap
                 "right" => new_x += delta,
                 _ => {}
             }
+            if state.confine_to_monitor.load(Ordering::SeqCst) {
+                (new_x, new_y) = confine_to_current_monitor(&window, new_x, new_y);
+            }
 
             let _ = window.set_position(Position::Physical(PhysicalPosition { x: new_x, y: new_y }));
         }
     }
 }
 
+#[tauri::command]
+fn nudge_window_animated(
+    direction: &str,
+    distance: i32,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, ToggleState>,
+    tasks: tauri::State<'_, BackgroundTasks>,
+) -> Result<(), String> {
+    const STEPS: i32 = 8;
+    const DURATION_MS: u64 = 150;
+
+    // Repeated animated nudges each add their distance, so none are dropped as duplicates
+    let _op = lock_position_op(&state, format!("nudge-animated:{}:{}", direction, distance));
+    let window = app.get_webview_window("main").ok_or("Main window not found")?;
+    let start = window.outer_position().map_err(|e| e.to_string())?;
+    let distance = if distance == 0 { 50 } else { distance };
+    let (dx, dy) = match direction {
+        "up" => (0, -distance),
+        "down" => (0, distance),
+        "left" => (-distance, 0),
+        "right" => (distance, 0),
+        _ => return Err(format!("Unknown direction '{}'", direction)),
+    };
+    // Shorten the slide so it stops at the monitor edge
+    let (dx, dy) = if state.confine_to_monitor.load(Ordering::SeqCst) {
+        let (x, y) = confine_to_current_monitor(&window, start.x + dx, start.y + dy);
+        (x - start.x, y - start.y)
+    } else {
+        (dx, dy)
+    };
+
+    // Registering under a fixed name cancels any animation still in flight
+    let handle = tauri::async_runtime::spawn(async move {
+        for step in 1..=STEPS {
+            tokio::time::sleep(Duration::from_millis(DURATION_MS / STEPS as u64)).await;
+            // Ease-out cubic: fast start, gentle landing
+            let t = step as f64 / STEPS as f64;
+            let eased = 1.0 - (1.0 - t).powi(3);
+            // Each frame takes the positioning lock so it can't interleave with a move or resize
+            let state = window.state::<ToggleState>();
+            let _op = state.last_position_op.lock().unwrap();
+            let _ = window.set_position(Position::Physical(PhysicalPosition {
+                x: start.x + (dx as f64 * eased).round() as i32,
+                y: start.y + (dy as f64 * eased).round() as i32,
+            }));
+        }
+    });
+    tasks.track("nudge-animation", handle);
+    Ok(())
+}
+
 #[tauri::command]
 fn toggle_window_visibility(state: tauri::State<ToggleState>, app: tauri::AppHandle) -> bool {
     // Debounce rapid repeats from key auto-repeat: allow only every 350ms
@@ -129,9 +902,10 @@ fn toggle_window_visibility(state: tauri::State<ToggleState>, app: tauri::AppHan
             // Restore original window properties to initial state
             let _ = window.set_always_on_top(true);
             let _ = window.set_decorations(false);
-            let _ = window.set_content_protected(true);
             let _ = window.set_skip_taskbar(true);
             let _ = window.set_ignore_cursor_events(false);
+            // Last, since changing decorations can reset display affinity
+            reassert_content_protection(&app, expected_protection(&app));
         } else {
             // Change state to hidden without hiding window
             let _ = window.set_ignore_cursor_events(true);
@@ -141,375 +915,5858 @@ fn toggle_window_visibility(state: tauri::State<ToggleState>, app: tauri::AppHan
     now_visible
 }
 
-#[tauri::command]
-fn resize_window(width: f64, height: f64, app: tauri::AppHandle) {
-    if let Some(window) = app.get_webview_window("main") {
-        let _ = window.set_size(Size::Logical(LogicalSize { width, height }));
+// Show a window; with focus_on_show off it appears without being activated
+fn show_window(app: &tauri::AppHandle, window: &tauri::WebviewWindow) {
+    let focus = app.try_state::<ToggleState>().is_none_or(|state| state.focus_on_show.load(Ordering::SeqCst));
+    if focus {
+        let _ = window.show();
+    } else {
+        show_window_inactive(window);
+    }
+}
+
+#[cfg(windows)]
+fn show_window_inactive(window: &tauri::WebviewWindow) {
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::UI::WindowsAndMessaging::{ShowWindow, SW_SHOWNOACTIVATE};
+
+    match window.hwnd() {
+        Ok(hwnd) => unsafe {
+            let _ = ShowWindow(HWND(hwnd.0), SW_SHOWNOACTIVATE);
+        },
+        Err(_) => {
+            let _ = window.show();
+        }
     }
 }
 
+// Elsewhere the window manager decides; a plain show is the least intrusive option available
+#[cfg(not(windows))]
+fn show_window_inactive(window: &tauri::WebviewWindow) {
+    let _ = window.show();
+}
+
+// Whether the overlay takes keyboard focus when toggled visible or re-shown after a capture.
+// Off keeps the editor focused (no-activate show on Windows); click the overlay to type into it.
 #[tauri::command]
-fn capture_area(x: i32, y: i32, width: u32, height: u32) -> Result<String, String> {
-    let screens = Screen::all().map_err(|e| e.to_string())?;
-    let screen = screens.get(0).ok_or("No screens found")?;
+fn set_focus_on_show(enabled: bool, state: tauri::State<'_, ToggleState>) -> bool {
+    state.focus_on_show.store(enabled, Ordering::SeqCst);
+    enabled
+}
 
-    let image = screen
-        .capture_area(x, y, width, height)
-        .map_err(|e| e.to_string())?;
+#[derive(Clone, serde::Serialize)]
+struct ProtectionStatus {
+    expected: bool,
+    verified: Option<bool>, // None where the platform can't read protection back
+    affinity: Option<u32>, // Raw Windows display affinity
+}
 
-    let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, image.into_raw())
-        .ok_or("Failed to convert image")?;
+#[cfg(windows)]
+fn display_affinity(window: &tauri::WebviewWindow) -> Option<u32> {
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::UI::WindowsAndMessaging::GetWindowDisplayAffinity;
 
-    let filename = format!("{}.png", Uuid::new_v4());
-    let mut path = std::env::temp_dir();
-    path.push("tauri_gemini");
-    fs::create_dir_all(&path).ok();
-    path.push(filename);
+    let hwnd = HWND(window.hwnd().ok()?.0);
+    let mut affinity = 0u32;
+    unsafe { GetWindowDisplayAffinity(hwnd, &mut affinity) }.ok()?;
+    Some(affinity)
+}
+
+#[cfg(not(windows))]
+fn display_affinity(_window: &tauri::WebviewWindow) -> Option<u32> {
+    None
+}
 
-    buffer.save(&path).map_err(|e| e.to_string())?;
+// Protection the active stealth profile calls for
+fn expected_protection(app: &tauri::AppHandle) -> bool {
+    let state = app.state::<ToggleState>();
+    let profile = state.stealth_profile.lock().unwrap().clone();
+    stealth_flags(&profile).is_none_or(|flags| flags.content_protected)
+}
 
-    Ok(path.to_string_lossy().to_string())
+// Re-apply content protection to the overlay and read it back, since some Windows builds drop display
+// affinity when the window is re-shown or its decorations change. Emits "protection-warning" if it didn't stick.
+fn reassert_content_protection(app: &tauri::AppHandle, expected: bool) -> ProtectionStatus {
+    let Some(window) = app.get_webview_window("main") else {
+        return ProtectionStatus { expected, verified: None, affinity: None };
+    };
+    let _ = window.set_content_protected(expected);
+    let affinity = display_affinity(&window);
+    // WDA_NONE is 0; WDA_MONITOR and WDA_EXCLUDEFROMCAPTURE both keep the overlay out of captures
+    let verified = affinity.map(|affinity| (affinity != 0) == expected);
+    let status = ProtectionStatus { expected, verified, affinity };
+    if verified == Some(false) {
+        let _ = app.emit("protection-warning", status.clone());
+    }
+    status
 }
 
+// Re-apply and check the overlay's content protection for the active stealth profile
 #[tauri::command]
-fn capture_full_screen() -> Result<String, String> {
-    let binding = Screen::all().map_err(|e| e.to_string())?;
-    let screen = binding.get(0).ok_or("No screens found")?;
+fn verify_content_protection(app: tauri::AppHandle) -> ProtectionStatus {
+    reassert_content_protection(&app, expected_protection(&app))
+}
 
-    let image = screen.capture().map_err(|e| e.to_string())?;
-    let (width, height) = (image.width(), image.height());
+#[tauri::command]
+fn apply_stealth_profile(profile: String, state: tauri::State<ToggleState>, app: tauri::AppHandle) -> Result<String, String> {
+    let flags = stealth_flags(&profile)
+        .ok_or_else(|| format!("Unknown stealth profile '{}'. Use max, visible-demo, or interactive.", profile))?;
+    let window = app.get_webview_window("main").ok_or("Main window not found")?;
 
-    let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, image.into_raw())
-        .ok_or("Failed to convert image")?;
+    window.set_always_on_top(flags.always_on_top).map_err(|e| e.to_string())?;
+    window.set_decorations(flags.decorations).map_err(|e| e.to_string())?;
+    window.set_skip_taskbar(flags.skip_taskbar).map_err(|e| e.to_string())?;
+    window.set_ignore_cursor_events(flags.ignore_cursor).map_err(|e| e.to_string())?;
+    // Last, since changing decorations can reset display affinity
+    reassert_content_protection(&app, flags.content_protected);
 
-    let filename = format!("{}.png", Uuid::new_v4());
-    let mut path = std::env::temp_dir();
-    path.push("tauri_gemini");
-    fs::create_dir_all(&path).ok();
-    path.push(filename);
+    // Keep the visibility toggle in sync with the click-through state
+    state.visible.store(!flags.ignore_cursor, Ordering::SeqCst);
+    *state.stealth_profile.lock().map_err(|_| "Lock poisoned")? = profile.clone();
+    Ok(profile)
+}
 
-    buffer.save(&path).map_err(|e| e.to_string())?;
-    Ok(path.to_string_lossy().to_string())
+#[tauri::command]
+fn get_stealth_profile(state: tauri::State<ToggleState>) -> Result<String, String> {
+    Ok(state.stealth_profile.lock().map_err(|_| "Lock poisoned")?.clone())
 }
 
+const FOCUS_DIM_LABEL: &str = "focus-dim";
+
+// Show or remove a click-through, capture-protected dimmer behind the overlay
 #[tauri::command]
-fn set_gemini_api_key(key: String, cfg: tauri::State<'_, AppConfig>) -> Result<(), String> {
-    // Store in memory and set environment for underlying client
-    {
-        let mut api_key_guard = cfg.api_key.lock().map_err(|_| "Lock poisoned")?;
-        *api_key_guard = if key.trim().is_empty() { None } else { Some(key.clone()) };
+async fn focus_mode(enabled: bool, app: tauri::AppHandle) -> Result<bool, String> {
+    if !enabled {
+        if let Some(dim) = app.get_webview_window(FOCUS_DIM_LABEL) {
+            dim.destroy().map_err(|e| e.to_string())?;
+        }
+        return Ok(false);
     }
-    if key.trim().is_empty() {
-        std::env::remove_var("GEMINI_API_KEY");
-    } else {
-        std::env::set_var("GEMINI_API_KEY", key);
+    if app.get_webview_window(FOCUS_DIM_LABEL).is_some() {
+        return Ok(true);
     }
 
-    // Persist to Windows user environment and broadcast change
-    #[cfg(windows)]
-    {
-        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-        if let Ok(env) = hkcu.open_subkey_with_flags("Environment", winreg::enums::KEY_SET_VALUE) {
-            if let Err(e) = env.set_value("GEMINI_API_KEY", &std::env::var("GEMINI_API_KEY").unwrap_or_default()) {
-                eprintln!("Failed to write GEMINI_API_KEY to registry: {}", e);
-            }
-        }
+    let monitor = app
+        .primary_monitor()
+        .map_err(|e| e.to_string())?
+        .ok_or("No primary monitor found")?;
+    let scale = monitor.scale_factor();
+    let (pos, size) = (monitor.position(), monitor.size());
 
-        unsafe {
-            let _ = SendMessageTimeoutW(
-                HWND_BROADCAST,
-                WM_SETTINGCHANGE,
-                WPARAM(0),
-                LPARAM(w!("Environment").as_ptr() as isize),
-                SMTO_ABORTIFHUNG,
-                5000,
-                None,
-            );
-        }
+    let url = "data:text/html,<body style=\"margin:0;background:rgba(0,0,0,0.6)\"></body>"
+        .parse::<tauri::Url>()
+        .map_err(|e| e.to_string())?;
+    let builder = tauri::WebviewWindowBuilder::new(&app, FOCUS_DIM_LABEL, tauri::WebviewUrl::External(url))
+        .position(pos.x as f64 / scale, pos.y as f64 / scale)
+        .inner_size(size.width as f64 / scale, size.height as f64 / scale)
+        .decorations(false)
+        .resizable(false)
+        .shadow(false)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .content_protected(true)
+        .focused(false)
+        .focusable(false);
+    #[cfg(not(target_os = "macos"))]
+    let builder = builder.transparent(true);
+    let dim = builder.build().map_err(|e| e.to_string())?;
+    dim.set_ignore_cursor_events(true).map_err(|e| e.to_string())?;
+
+    // Re-assert topmost on the overlay so it stacks above the dimmer
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.set_always_on_top(false);
+        let _ = window.set_always_on_top(true);
     }
-    Ok(())
+    Ok(true)
+}
+
+const GUIDED_CAPTURE_LABEL: &str = "guided-capture";
+const GUIDED_CAPTURE_HOST: &str = "guided-capture.invalid"; // Never resolves; the page "navigates" here to report back
+
+// Grid, rulers and a live readout; dragging picks a rectangle, Escape cancels
+const GUIDED_CAPTURE_PAGE: &str = r##"<html><body style="margin:0;overflow:hidden;cursor:crosshair;background:rgba(0,0,0,0.25);font:12px monospace">
+<canvas id="c"></canvas>
+<div id="r" style="position:fixed;left:8px;top:8px;padding:4px 6px;background:rgba(0,0,0,0.8);color:#fff;border-radius:4px"></div>
+<script>
+const c = document.getElementById("c"), g = c.getContext("2d"), r = document.getElementById("r"), step = STEP;
+let start = null, mouse = { x: 0, y: 0 };
+function rect() {
+  if (!start) return null;
+  return { x: Math.min(start.x, mouse.x), y: Math.min(start.y, mouse.y), w: Math.abs(mouse.x - start.x), h: Math.abs(mouse.y - start.y) };
+}
+function draw() {
+  g.clearRect(0, 0, c.width, c.height);
+  g.strokeStyle = "rgba(255,255,255,0.15)";
+  g.beginPath();
+  for (let x = 0; x < c.width; x += step) { g.moveTo(x + 0.5, 0); g.lineTo(x + 0.5, c.height); }
+  for (let y = 0; y < c.height; y += step) { g.moveTo(0, y + 0.5); g.lineTo(c.width, y + 0.5); }
+  g.stroke();
+  g.fillStyle = "rgba(255,255,255,0.7)";
+  for (let x = 0; x < c.width; x += step * 5) g.fillText(x, x + 2, 10);
+  for (let y = step * 5; y < c.height; y += step * 5) g.fillText(y, 2, y - 2);
+  const q = rect();
+  if (q) { g.strokeStyle = "#4af"; g.lineWidth = 2; g.strokeRect(q.x, q.y, q.w, q.h); g.lineWidth = 1; }
+  r.textContent = q ? `x ${q.x}  y ${q.y}  ${q.w} x ${q.h}` : `x ${mouse.x}  y ${mouse.y}  (drag to select, Esc to cancel)`;
+}
+function done(q) {
+  location.href = q ? `https://HOST/select?x=${q.x}&y=${q.y}&w=${q.w}&h=${q.h}` : "https://HOST/cancel";
+}
+onmousemove = (e) => { mouse = { x: e.clientX, y: e.clientY }; draw(); };
+onmousedown = (e) => { start = { x: e.clientX, y: e.clientY }; mouse = start; draw(); };
+onmouseup = () => { const q = rect(); start = null; if (q && q.w > 2 && q.h > 2) done(q); else draw(); };
+onkeydown = (e) => { if (e.key === "Escape") done(null); };
+onresize = () => { c.width = innerWidth; c.height = innerHeight; draw(); };
+onresize();
+</script></body></html>"##;
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[derive(serde::Serialize)]
+struct GuidedSelection {
+    x: i32, // Logical pixels on the primary monitor, as capture_area takes them
+    y: i32,
+    width: u32,
+    height: u32,
+    scale_factor: f32, // Multiply by this for physical pixels
+}
+
+// Show a capture-protected full-screen grid over the primary monitor and wait for a dragged rectangle.
+// Returns None when cancelled (Escape, window closed or no choice within two minutes).
+#[tauri::command]
+async fn begin_guided_capture(grid_step: Option<u32>, app: tauri::AppHandle) -> Result<Option<GuidedSelection>, String> {
+    if let Some(previous) = app.get_webview_window(GUIDED_CAPTURE_LABEL) {
+        previous.destroy().map_err(|e| e.to_string())?;
+    }
+    let monitor = app
+        .primary_monitor()
+        .map_err(|e| e.to_string())?
+        .ok_or("No primary monitor found")?;
+    let scale = monitor.scale_factor();
+    let (pos, size) = (monitor.position(), monitor.size());
+
+    let page = GUIDED_CAPTURE_PAGE
+        .replace("STEP", &grid_step.unwrap_or(20).clamp(5, 200).to_string())
+        .replace("HOST", GUIDED_CAPTURE_HOST);
+    let url = format!("data:text/html,{}", percent_encode(&page))
+        .parse::<tauri::Url>()
+        .map_err(|e| e.to_string())?;
+
+    // The page reports its result by navigating to the sentinel host, which is intercepted and blocked
+    let (sender, receiver) = tokio::sync::oneshot::channel::<Option<GuidedSelection>>();
+    let sender = Mutex::new(Some(sender));
+    let builder = tauri::WebviewWindowBuilder::new(&app, GUIDED_CAPTURE_LABEL, tauri::WebviewUrl::External(url))
+        .position(pos.x as f64 / scale, pos.y as f64 / scale)
+        .inner_size(size.width as f64 / scale, size.height as f64 / scale)
+        .decorations(false)
+        .resizable(false)
+        .shadow(false)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .content_protected(true)
+        .focused(true)
+        .on_navigation(move |url| {
+            if url.host_str() != Some(GUIDED_CAPTURE_HOST) {
+                return true;
+            }
+            let value = |key: &str| url.query_pairs().find(|(k, _)| k == key).and_then(|(_, v)| v.parse::<i64>().ok());
+            let selection = match (value("x"), value("y"), value("w"), value("h")) {
+                (Some(x), Some(y), Some(w), Some(h)) if url.path() == "/select" && w > 0 && h > 0 => Some(GuidedSelection {
+                    x: x as i32,
+                    y: y as i32,
+                    width: w as u32,
+                    height: h as u32,
+                    scale_factor: scale as f32,
+                }),
+                _ => None,
+            };
+            if let Some(sender) = sender.lock().unwrap().take() {
+                let _ = sender.send(selection);
+            }
+            false
+        });
+    #[cfg(not(target_os = "macos"))]
+    let builder = builder.transparent(true);
+    builder.build().map_err(|e| e.to_string())?;
+
+    let selection = tokio::time::timeout(Duration::from_secs(120), receiver).await.ok().and_then(|result| result.ok()).flatten();
+    // Gone before anything is captured, so the grid can never end up in a screenshot
+    if let Some(helper) = app.get_webview_window(GUIDED_CAPTURE_LABEL) {
+        helper.destroy().map_err(|e| e.to_string())?;
+    }
+    Ok(selection)
+}
+
+#[tauri::command]
+fn resize_window(width: f64, height: f64, state: tauri::State<ToggleState>, app: tauri::AppHandle) {
+    let Some(_op) = begin_position_op(&state, format!("resize:{}x{}", width, height)) else {
+        return;
+    };
+    let (width, height) = match *state.max_size.lock().unwrap() {
+        Some((max_width, max_height)) => (width.min(max_width), height.min(max_height)),
+        None => (width, height),
+    };
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.set_size(Size::Logical(LogicalSize { width, height }));
+    }
+}
+
+// Logical size of the peek-mode status strip
+const PEEK_SIZE: (f64, f64) = (320.0, 36.0);
+
+#[derive(Clone, serde::Serialize)]
+struct PeekMode {
+    enabled: bool,
+    model: String,
+    queue_length: usize,
+    ready: bool, // No AI request in flight
+    width: f64,
+    height: f64,
+}
+
+// What the peek strip shows right now, at the given window size
+fn peek_status(app: &tauri::AppHandle, width: f64, height: f64) -> Result<PeekMode, String> {
+    let cfg = app.state::<AppConfig>();
+    let (limit, slots) = cfg.request_slots.lock().map_err(|_| "Lock poisoned")?.clone();
+    let mode = PeekMode {
+        enabled: app.state::<ToggleState>().peek.load(Ordering::SeqCst),
+        model: cfg.model.lock().map_err(|_| "Lock poisoned")?.clone(),
+        queue_length: app.state::<ImageQueue>().images.lock().map_err(|_| "Lock poisoned")?.len(),
+        ready: slots.available_permits() == limit,
+        width,
+        height,
+    };
+    Ok(mode)
+}
+
+// While peeking, re-emit "peek-mode" whenever the model, queue length or ready state changes.
+// `shown` is the status the frontend already has, if any.
+fn start_peek_status(app: &tauri::AppHandle, shown: Option<PeekMode>) {
+    let poll_app = app.clone();
+    let handle = tauri::async_runtime::spawn(async move {
+        let mut last = shown.map(|mode| (mode.model, mode.queue_length, mode.ready));
+        loop {
+            if let Ok(mode) = peek_status(&poll_app, PEEK_SIZE.0, PEEK_SIZE.1) {
+                let current = (mode.model.clone(), mode.queue_length, mode.ready);
+                if last.as_ref() != Some(&current) {
+                    let _ = poll_app.emit("peek-mode", mode);
+                    last = Some(current);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    });
+    app.state::<BackgroundTasks>().track("peek-status", handle);
+}
+
+// Shrink the overlay to a thin status strip (model, queue count, ready indicator) or restore its
+// full size. Peek mode and the full size are persisted, so a restart while peeking comes back as the
+// strip; the frontend switches layouts on the "peek-mode" event, which repeats as the status changes.
+#[tauri::command]
+fn toggle_peek_mode(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, ToggleState>,
+    tasks: tauri::State<'_, BackgroundTasks>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<PeekMode, String> {
+    let window = app.get_webview_window("main").ok_or("Main window not found")?;
+    let scale = window.scale_factor().map_err(|e| e.to_string())?;
+    let enabled = !state.peek.load(Ordering::SeqCst);
+    let (width, height) = {
+        let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+        let size = if enabled {
+            let full = window.inner_size().map_err(|e| e.to_string())?.to_logical::<f64>(scale);
+            persisted.peek_restore_size = Some((full.width, full.height));
+            PEEK_SIZE
+        } else {
+            let size = persisted.peek_restore_size.unwrap_or((800.0, 600.0)); // tauri.conf.json default
+            match *state.max_size.lock().map_err(|_| "Lock poisoned")? {
+                Some((max_width, max_height)) => (size.0.min(max_width), size.1.min(max_height)),
+                None => size,
+            }
+        };
+        persisted.peeking = enabled;
+        save_persisted_config(&app, &persisted)?;
+        size
+    };
+    window.set_size(Size::Logical(LogicalSize { width, height })).map_err(|e| e.to_string())?;
+    state.peek.store(enabled, Ordering::SeqCst);
+
+    let mode = peek_status(&app, width, height)?;
+    let _ = app.emit("peek-mode", mode.clone());
+    if enabled {
+        start_peek_status(&app, Some(mode.clone()));
+    } else {
+        tasks.stop("peek-status");
+    }
+    Ok(mode)
+}
+
+#[derive(serde::Serialize)]
+struct WindowSizeSuggestion {
+    width: f64,
+    height: f64,
+    clamped: bool, // True when the max-window-size cap cut the estimate down
+}
+
+// Estimate a logical size that fits `text` without scrolling, from average glyph metrics
+#[tauri::command]
+fn suggest_window_size(text: String, font_size: Option<f64>, state: tauri::State<ToggleState>) -> Result<WindowSizeSuggestion, String> {
+    const MIN_WIDTH: f64 = 360.0;
+    const MAX_WIDTH: f64 = 960.0;
+    const CHROME_WIDTH: f64 = 48.0; // Horizontal padding around the answer
+    const CHROME_HEIGHT: f64 = 220.0; // Prompt box, buttons and padding above/below the answer
+
+    let font_size = font_size.unwrap_or(14.0).max(8.0);
+    let char_width = font_size * 0.6;
+    let line_height = font_size * 1.5;
+
+    let longest = text.lines().map(|line| line.chars().count()).max().unwrap_or(0) as f64;
+    let width = (longest * char_width + CHROME_WIDTH).clamp(MIN_WIDTH, MAX_WIDTH);
+    let per_line = ((width - CHROME_WIDTH) / char_width).floor().max(1.0);
+    // Long lines wrap, so count the visual rows each one takes
+    let rows: f64 = text
+        .lines()
+        .map(|line| (line.chars().count() as f64 / per_line).ceil().max(1.0))
+        .sum();
+    let height = rows * line_height + CHROME_HEIGHT;
+
+    let (width, height, clamped) = match *state.max_size.lock().map_err(|_| "Lock poisoned")? {
+        Some((max_width, max_height)) => (
+            width.min(max_width),
+            height.min(max_height),
+            width > max_width || height > max_height,
+        ),
+        None => (width, height, false),
+    };
+    Ok(WindowSizeSuggestion { width: width.round(), height: height.round(), clamped })
+}
+
+// Cap the overlay size for resize commands and OS-level drags alike; 0x0 removes the cap
+#[tauri::command]
+fn set_max_window_size(width: f64, height: f64, state: tauri::State<ToggleState>, app: tauri::AppHandle) -> Result<(), String> {
+    let bounds = (width > 0.0 && height > 0.0).then_some((width, height));
+    *state.max_size.lock().map_err(|_| "Lock poisoned")? = bounds;
+
+    let window = app.get_webview_window("main").ok_or("Main window not found")?;
+    window
+        .set_max_size(bounds.map(|(width, height)| Size::Logical(LogicalSize { width, height })))
+        .map_err(|e| e.to_string())?;
+    // Shrink immediately if the overlay is already larger than the new bounds
+    if let Some((max_width, max_height)) = bounds {
+        let scale = window.scale_factor().map_err(|e| e.to_string())?;
+        let current = window.inner_size().map_err(|e| e.to_string())?.to_logical::<f64>(scale);
+        if current.width > max_width || current.height > max_height {
+            window
+                .set_size(Size::Logical(LogicalSize {
+                    width: current.width.min(max_width),
+                    height: current.height.min(max_height),
+                }))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// Saved pixels per captured pixel once max_capture_dimension is applied; 1.0 when the frame fits
+fn saved_scale(max_side: Option<u32>, width: u32, height: u32) -> f64 {
+    match max_side {
+        Some(max_side) if width.max(height) > max_side => max_side as f64 / width.max(height) as f64,
+        _ => 1.0,
+    }
+}
+
+// Write a captured RGBA frame to a uniquely named PNG in the app's temp folder
+fn save_capture(cfg: &AppConfig, width: u32, height: u32, raw: Vec<u8>) -> Result<String, String> {
+    let strip = cfg.strip_watermark.load(Ordering::SeqCst);
+    let max_side = *cfg.max_capture_dimension.lock().map_err(|_| "Lock poisoned")?;
+    let strength = *cfg.capture_enhance.lock().map_err(|_| "Lock poisoned")?;
+
+    let filename = format!("{}.png", Uuid::new_v4());
+    let mut path = capture_dir(cfg);
+    path.push(filename);
+
+    // The pixel work and encoding run on the bounded image pool; the capturing thread waits for it
+    let pool = cfg.image_pool.lock().map_err(|_| "Lock poisoned")?.clone();
+    let (saved_width, saved_height) = pool.install(|| -> Result<(u32, u32), String> {
+        let mut buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, raw)
+            .ok_or("Failed to convert image")?;
+        if strip {
+            strip_watermark(&mut buffer);
+        }
+        let scale = saved_scale(max_side, width, height);
+        let buffer = if scale < 1.0 {
+            let (w, h) = (((width as f64 * scale) as u32).max(1), ((height as f64 * scale) as u32).max(1));
+            image::imageops::resize(&buffer, w, h, image::imageops::FilterType::Triangle)
+        } else {
+            buffer
+        };
+        buffer.save(&path).map_err(|e| e.to_string())?;
+        Ok(buffer.dimensions())
+    })?;
+    let _ = prune_temp_files(cfg, Some(&path));
+    let path = path.to_string_lossy().to_string();
+    record_event(cfg, "capture", json!({ "path": path, "width": saved_width, "height": saved_height }));
+
+    match strength {
+        Some(strength) => pool.install(|| enhance_contrast(&path, strength)),
+        None => Ok(path),
+    }
+}
+
+// Temp subdirectory for captures and generated images, per the current TempPolicy
+fn capture_dir(cfg: &AppConfig) -> PathBuf {
+    let prefix = cfg.persisted.lock().map(|p| p.temp_policy.prefix.clone()).unwrap_or_else(|_| TempPolicy::default().prefix);
+    let dir = std::env::temp_dir().join(prefix);
+    fs::create_dir_all(&dir).ok();
+    dir
+}
+
+#[derive(serde::Serialize)]
+struct TempStats {
+    dir: String,
+    files: usize,
+    total_bytes: u64,
+    removed_files: usize,
+    removed_bytes: u64,
+}
+
+// Files the app still needs: queued, pinned, the session image, the screen context and the last request's images
+fn temp_paths_in_use(queue: &ImageQueue, cfg: &AppConfig) -> Result<HashSet<PathBuf>, String> {
+    let mut in_use: HashSet<PathBuf> = queue.images.lock().map_err(|_| "Lock poisoned")?.iter().map(PathBuf::from).collect();
+    in_use.extend(queue.pinned.lock().map_err(|_| "Lock poisoned")?.iter().map(PathBuf::from));
+    in_use.extend(queue.session_image.lock().map_err(|_| "Lock poisoned")?.iter().map(PathBuf::from));
+    in_use.extend(cfg.screen_context.lock().map_err(|_| "Lock poisoned")?.iter().map(|context| PathBuf::from(&context.path)));
+    if let Some(conv) = cfg.app.try_state::<Conversation>() {
+        if let Some(last) = conv.last_request.lock().map_err(|_| "Lock poisoned")?.as_ref() {
+            in_use.extend(last.image_paths.iter().map(PathBuf::from));
+        }
+    }
+    Ok(in_use)
+}
+
+// The app's own captures in the temp folder: PNGs other than LaTeX renders
+fn is_capture_file(name: &str) -> bool {
+    name.ends_with(".png") && !name.contains("latex")
+}
+
+// Delete captures past the policy's age, then the oldest ones until the folder fits its size limit.
+// Only PNG captures are pruned (LaTeX renders and work folders stay); `keep` (the capture just written)
+// and every file from temp_paths_in_use are never removed.
+fn prune_temp_files(cfg: &AppConfig, keep: Option<&std::path::Path>) -> Result<TempStats, String> {
+    let policy = cfg.persisted.lock().map_err(|_| "Lock poisoned")?.temp_policy.clone();
+    let mut in_use = match cfg.app.try_state::<ImageQueue>() {
+        Some(queue) => temp_paths_in_use(&queue, cfg)?,
+        None => HashSet::new(),
+    };
+    in_use.extend(keep.map(PathBuf::from));
+    let dir = capture_dir(cfg);
+    let mut files: Vec<(PathBuf, u64, std::time::SystemTime)> = fs::read_dir(&dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let meta = entry.metadata().ok()?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let capture = meta.is_file() && is_capture_file(&name);
+            capture.then(|| (entry.path(), meta.len(), meta.modified().unwrap_or(std::time::UNIX_EPOCH)))
+        })
+        .collect();
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    let now = std::time::SystemTime::now();
+    let mut total_bytes: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let (mut removed_files, mut removed_bytes) = (0, 0);
+    let mut remaining = Vec::with_capacity(files.len());
+    for (path, size, modified) in files {
+        let age = now.duration_since(modified).unwrap_or_default().as_secs();
+        let expired = policy.max_age_seconds.is_some_and(|max_age| age > max_age);
+        let over_size = policy.max_total_bytes.is_some_and(|max_total| total_bytes > max_total);
+        if (expired || over_size) && !in_use.contains(&path) && fs::remove_file(&path).is_ok() {
+            total_bytes -= size;
+            removed_files += 1;
+            removed_bytes += size;
+        } else {
+            remaining.push(path);
+        }
+    }
+    Ok(TempStats { dir: dir.to_string_lossy().to_string(), files: remaining.len(), total_bytes, removed_files, removed_bytes })
+}
+
+// Name the temp subdirectory and limit how old / how large its contents may get; pruning runs
+// after every capture and once now. Files already in a previous prefix's folder are left alone.
+#[tauri::command]
+fn set_temp_policy(
+    prefix: String,
+    max_age_seconds: Option<u64>,
+    max_total_bytes: Option<u64>,
+    app: tauri::AppHandle,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<TempStats, String> {
+    let prefix = prefix.trim().to_string();
+    if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err("Prefix must be non-empty and use only letters, digits, '_' or '-'".to_string());
+    }
+    {
+        let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+        persisted.temp_policy = TempPolicy { prefix, max_age_seconds, max_total_bytes };
+        save_persisted_config(&app, &persisted)?;
+    }
+    prune_temp_files(&cfg, None)
+}
+
+#[derive(serde::Serialize)]
+struct PurgeResult {
+    removed_files: usize,
+    removed_bytes: u64,
+    skipped_in_use: usize, // Old files kept because the queue, a pin or the screen context still uses them
+}
+
+// Delete captures (see is_capture_file) last modified before `timestamp_ms` (Unix milliseconds)
+#[tauri::command]
+fn purge_temp_before(
+    timestamp_ms: u64,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<PurgeResult, String> {
+    let cutoff = std::time::UNIX_EPOCH + Duration::from_millis(timestamp_ms);
+    let in_use = temp_paths_in_use(&queue, &cfg)?;
+
+    let mut result = PurgeResult { removed_files: 0, removed_bytes: 0, skipped_in_use: 0 };
+    for entry in fs::read_dir(capture_dir(&cfg)).map_err(|e| e.to_string())?.flatten() {
+        let Ok(meta) = entry.metadata() else { continue };
+        let capture = meta.is_file() && is_capture_file(&entry.file_name().to_string_lossy());
+        let old = capture && meta.modified().is_ok_and(|modified| modified < cutoff);
+        if !old {
+            continue;
+        }
+        if in_use.contains(&entry.path()) {
+            result.skipped_in_use += 1;
+        } else if fs::remove_file(entry.path()).is_ok() {
+            result.removed_files += 1;
+            result.removed_bytes += meta.len();
+        }
+    }
+    Ok(result)
+}
+
+// Stretch luminance to the full range and lift shadows so dark-themed screens read better.
+// Writes an enhanced copy next to the original and returns its path.
+fn enhance_contrast(path: &str, strength: f32) -> Result<String, String> {
+    let strength = strength.clamp(0.0, 1.0);
+    let mut img = image::open(path).map_err(|e| e.to_string())?.to_rgba8();
+
+    let mut histogram = [0u64; 256];
+    for p in img.pixels() {
+        let luma = (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) as usize;
+        histogram[luma.min(255)] += 1;
+    }
+    let total = (img.width() as u64 * img.height() as u64).max(1);
+    let percentile = |fraction: f64| {
+        let target = (total as f64 * fraction) as u64;
+        let mut seen = 0;
+        for (level, count) in histogram.iter().enumerate() {
+            seen += count;
+            if seen > target {
+                return level as f32;
+            }
+        }
+        255.0
+    };
+    let (low, high) = (percentile(0.01), percentile(0.99));
+    let range = (high - low).max(1.0);
+    let mean = histogram.iter().enumerate().map(|(l, c)| l as u64 * c).sum::<u64>() as f32 / total as f32;
+    // Gamma < 1 brightens mid-tones; only applied when the screen is predominantly dark
+    let gamma = if mean < 96.0 { 1.0 / (1.0 + strength) } else { 1.0 };
+
+    for p in img.pixels_mut() {
+        for c in 0..3 {
+            let original = p[c] as f32;
+            let stretched = ((original - low) / range).clamp(0.0, 1.0).powf(gamma) * 255.0;
+            p[c] = (original + strength * (stretched - original)).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    let enhanced = path.trim_end_matches(".png").to_string() + "_enhanced.png";
+    img.save(&enhanced).map_err(|e| e.to_string())?;
+    Ok(enhanced)
+}
+
+// Title fragments of windows that only exist while a call is live (not the idle app windows).
+// A pattern matches when every "+"-separated fragment appears in the lowercased title.
+const MEETING_TITLE_PATTERNS: &[&str] = &[
+    "zoom meeting",
+    "zoom webinar",
+    "google meet",
+    "meet - ",
+    "webex meeting",
+    "| webex",
+    "microsoft teams+meeting",
+    "microsoft teams+call",
+];
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn is_meeting_title(title: &str) -> bool {
+    let title = title.to_lowercase();
+    MEETING_TITLE_PATTERNS
+        .iter()
+        .any(|pattern| pattern.split('+').all(|fragment| title.contains(fragment)))
+}
+
+// Title of the window that currently has focus; None when it has no title
+#[cfg(windows)]
+fn foreground_window_title() -> Option<String> {
+    use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowTextW};
+
+    let mut buf = [0u16; 512];
+    let len = unsafe { GetWindowTextW(GetForegroundWindow(), &mut buf) };
+    let title = String::from_utf16_lossy(&buf[..len.max(0) as usize]);
+    (!title.trim().is_empty()).then_some(title)
+}
+
+#[cfg(not(windows))]
+fn foreground_window_title() -> Option<String> {
+    None
+}
+
+// Title of the foreground window when it looks like a live call
+fn foreground_meeting_window() -> Option<String> {
+    foreground_window_title().filter(|title| is_meeting_title(title))
+}
+
+// The foreground window's title unless it is one of ours (the overlay was focused, not the captured app)
+fn captured_window_title(app: &tauri::AppHandle) -> Option<String> {
+    let title = foreground_window_title()?;
+    let ours = app.webview_windows().values().any(|window| window.title().is_ok_and(|own| own == title));
+    (!ours).then_some(title)
+}
+
+// Title of the first visible, non-minimized top-level window that looks like a live call
+#[cfg(windows)]
+fn video_call_window() -> Option<String> {
+    use windows::Win32::Foundation::{BOOL, HWND};
+    use windows::Win32::UI::WindowsAndMessaging::{EnumWindows, GetWindowTextW, IsIconic, IsWindowVisible};
+
+    unsafe extern "system" fn collect(hwnd: HWND, titles: LPARAM) -> BOOL {
+        let titles = &mut *(titles.0 as *mut Vec<String>);
+        if IsWindowVisible(hwnd).as_bool() && !IsIconic(hwnd).as_bool() {
+            let mut buf = [0u16; 512];
+            let len = GetWindowTextW(hwnd, &mut buf);
+            if len > 0 {
+                titles.push(String::from_utf16_lossy(&buf[..len as usize]));
+            }
+        }
+        true.into()
+    }
+
+    let mut titles: Vec<String> = Vec::new();
+    unsafe {
+        let _ = EnumWindows(Some(collect), LPARAM(&mut titles as *mut Vec<String> as isize));
+    }
+    titles.into_iter().find(|t| is_meeting_title(t))
+}
+
+#[cfg(not(windows))]
+fn video_call_window() -> Option<String> {
+    None
+}
+
+// Block a capture or image send while the guard is on and a call is visible, unless the user just confirmed
+fn ensure_no_video_call(cfg: &AppConfig) -> Result<(), String> {
+    if !cfg.video_call_guard.load(Ordering::SeqCst) || cfg.video_call_confirmed.swap(false, Ordering::SeqCst) {
+        return Ok(());
+    }
+    match video_call_window() {
+        Some(title) => Err(format!("Video call detected (\"{}\"); confirm before capturing or sending screenshots", title)),
+        None => Ok(()),
+    }
+}
+
+#[derive(serde::Serialize)]
+struct VideoCallCheck {
+    detected: bool,
+    window_title: Option<String>,
+    guard_enabled: bool,
+}
+
+#[tauri::command]
+fn check_video_call(cfg: tauri::State<'_, AppConfig>) -> VideoCallCheck {
+    let window_title = video_call_window();
+    VideoCallCheck {
+        detected: window_title.is_some(),
+        window_title,
+        guard_enabled: cfg.video_call_guard.load(Ordering::SeqCst),
+    }
+}
+
+#[tauri::command]
+fn set_video_call_guard(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> bool {
+    cfg.video_call_guard.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+// Allow the next capture through despite a detected call
+#[tauri::command]
+fn confirm_video_call_capture(cfg: tauri::State<'_, AppConfig>) {
+    cfg.video_call_confirmed.store(true, Ordering::SeqCst);
+}
+
+#[derive(serde::Serialize)]
+struct MeetingLockdown {
+    enabled: bool,
+    patterns: &'static [&'static str],
+}
+
+// Lockdown reads the foreground window title, which only Windows reports; elsewhere it would never trigger
+#[tauri::command]
+fn set_meeting_lockdown(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> Result<MeetingLockdown, String> {
+    if enabled && !cfg!(windows) {
+        return Err("Meeting lockdown is unsupported on this platform".to_string());
+    }
+    cfg.meeting_lockdown.store(enabled, Ordering::SeqCst);
+    Ok(MeetingLockdown { enabled, patterns: MEETING_TITLE_PATTERNS })
+}
+
+#[tauri::command]
+fn get_meeting_lockdown(cfg: tauri::State<'_, AppConfig>) -> MeetingLockdown {
+    MeetingLockdown { enabled: cfg.meeting_lockdown.load(Ordering::SeqCst), patterns: MEETING_TITLE_PATTERNS }
+}
+
+// Let the next AI call through despite a meeting in the foreground
+#[tauri::command]
+fn override_meeting_lockdown(cfg: tauri::State<'_, AppConfig>) {
+    cfg.meeting_lockdown_override.store(true, Ordering::SeqCst);
+}
+
+// Run a screen grab with all visible app windows hidden, when enabled, restoring them afterwards.
+// The video call guard is checked first so a blocked capture never touches the screen.
+async fn with_app_windows_hidden<T>(
+    app: &tauri::AppHandle,
+    cfg: &AppConfig,
+    grab: impl std::future::Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    ensure_no_video_call(cfg)?;
+    if !cfg.hide_windows_on_capture.load(Ordering::SeqCst) {
+        return grab.await;
+    }
+
+    let mut hidden: Vec<_> = app
+        .webview_windows()
+        .into_values()
+        .filter(|window| window.is_visible().unwrap_or(false) && window.hide().is_ok())
+        .collect();
+    // Restore auxiliary windows first so the overlay ends up on top again
+    hidden.sort_by_key(|window| window.label() == "main");
+    if !hidden.is_empty() {
+        // Give the compositor a moment to repaint without our windows
+        tokio::time::sleep(Duration::from_millis(150)).await;
+    }
+    if let Ok(mut last) = cfg.last_hidden_windows.lock() {
+        *last = hidden.iter().map(|window| window.label().to_string()).collect();
+    }
+
+    let result = grab.await;
+    for window in &hidden {
+        show_window(app, window);
+    }
+    if hidden.iter().any(|window| window.label() == "main") {
+        reassert_content_protection(app, expected_protection(app));
+    }
+    result
+}
+
+// A frame whose sampled pixels are all (near) black or fully transparent: a failed grab, not a screen
+fn is_blank_frame(image: &screenshots::image::RgbaImage) -> bool {
+    const SAMPLES: u32 = 64;
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return true;
+    }
+    (0..SAMPLES * SAMPLES).all(|i| {
+        let (sx, sy) = (i % SAMPLES, i / SAMPLES);
+        let pixel = image.get_pixel(sx * (width - 1) / (SAMPLES - 1), sy * (height - 1) / (SAMPLES - 1));
+        pixel[3] == 0 || pixel.0[..3].iter().all(|channel| *channel <= 2)
+    })
+}
+
+// Run a screen grab, retrying (up to the configured count) while it comes back blank. GPU hiccups
+// and secure-desktop transitions occasionally yield an all-black buffer that would be sent silently.
+async fn retry_blank(
+    cfg: &AppConfig,
+    mut grab: impl FnMut() -> Result<screenshots::image::RgbaImage, String>,
+) -> Result<screenshots::image::RgbaImage, String> {
+    let retries = cfg.blank_frame_retries.load(Ordering::SeqCst);
+    let mut image = grab()?;
+    for _ in 0..retries {
+        if !is_blank_frame(&image) {
+            return Ok(image);
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        image = grab()?;
+    }
+    if retries > 0 && is_blank_frame(&image) {
+        return Err(format!("Screen capture came back black {} times; try again", retries + 1));
+    }
+    Ok(image)
+}
+
+// How many times a blank (all-black) capture is retried before failing; 0 accepts any frame
+#[tauri::command]
+fn set_blank_frame_retries(retries: u32, cfg: tauri::State<'_, AppConfig>) -> Result<u32, String> {
+    if retries > 10 {
+        return Err("Retry at most 10 times".to_string());
+    }
+    cfg.blank_frame_retries.store(retries, Ordering::SeqCst);
+    Ok(retries)
+}
+
+#[tauri::command]
+fn set_hide_windows_on_capture(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> bool {
+    cfg.hide_windows_on_capture.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+#[tauri::command]
+fn get_last_hidden_windows(cfg: tauri::State<'_, AppConfig>) -> Result<Vec<String>, String> {
+    Ok(cfg.last_hidden_windows.lock().map_err(|_| "Lock poisoned")?.clone())
+}
+
+// Detect a faint overlay (many pixels just off the background colour) and flatten it into the background.
+// Only cells where faint pixels cluster (a corner logo, repeated tiles) are touched, and faint pixels next to
+// real content are kept, since those are anti-aliased text edges. Returns whether anything was stripped.
+fn strip_watermark(img: &mut image::RgbaImage) -> bool {
+    const MIN_DELTA: i32 = 3; // Below this is compression noise
+    const MAX_DELTA: i32 = 28; // Above this is real content
+    const MIN_COVERAGE: f64 = 0.02; // Fraction of near-background pixels that signals a watermark
+    const CELL: u32 = 32;
+    const MIN_CELL_COVERAGE: f64 = 0.05; // Faint fraction at which a cell counts as part of the watermark
+    const EDGE_RADIUS: i64 = 2; // Faint pixels this close to content are anti-aliasing
+
+    let luma = |p: &Rgba<u8>| (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) as i32;
+    let mut histogram = [0u64; 256];
+    for p in img.pixels() {
+        histogram[luma(p).clamp(0, 255) as usize] += 1;
+    }
+    let background = histogram.iter().enumerate().max_by_key(|(_, count)| **count).map(|(level, _)| level as i32).unwrap_or(0);
+
+    let total = (img.width() as u64 * img.height() as u64).max(1);
+    let faint: u64 = histogram
+        .iter()
+        .enumerate()
+        .filter(|(level, _)| (MIN_DELTA..=MAX_DELTA).contains(&(*level as i32 - background).abs()))
+        .map(|(_, count)| count)
+        .sum();
+    if (faint as f64 / total as f64) < MIN_COVERAGE {
+        return false;
+    }
+
+    // Mean colour of true background pixels, so tinted themes stay tinted
+    let (mut sum, mut n) = ([0u64; 3], 0u64);
+    for p in img.pixels().filter(|p| luma(p) == background) {
+        for c in 0..3 {
+            sum[c] += p[c] as u64;
+        }
+        n += 1;
+    }
+    let fill = Rgba([(sum[0] / n.max(1)) as u8, (sum[1] / n.max(1)) as u8, (sum[2] / n.max(1)) as u8, 255]);
+
+    let (width, height) = img.dimensions();
+    let delta: Vec<i32> = img.pixels().map(|p| (luma(p) - background).abs()).collect();
+    let is_content = |x: i64, y: i64| {
+        x >= 0 && y >= 0 && x < width as i64 && y < height as i64 && delta[(y as u32 * width + x as u32) as usize] > MAX_DELTA
+    };
+    let mut stripped = false;
+    for cell_y in (0..height).step_by(CELL as usize) {
+        for cell_x in (0..width).step_by(CELL as usize) {
+            let (cell_w, cell_h) = (CELL.min(width - cell_x), CELL.min(height - cell_y));
+            let cell_faint = (cell_y..cell_y + cell_h)
+                .flat_map(|y| (cell_x..cell_x + cell_w).map(move |x| (x, y)))
+                .filter(|(x, y)| (MIN_DELTA..=MAX_DELTA).contains(&delta[(y * width + x) as usize]))
+                .count();
+            if (cell_faint as f64) < (cell_w * cell_h) as f64 * MIN_CELL_COVERAGE {
+                continue;
+            }
+            for y in cell_y..cell_y + cell_h {
+                for x in cell_x..cell_x + cell_w {
+                    if delta[(y * width + x) as usize] > MAX_DELTA {
+                        continue;
+                    }
+                    let (xi, yi) = (x as i64, y as i64);
+                    let near_content = (-EDGE_RADIUS..=EDGE_RADIUS)
+                        .any(|dy| (-EDGE_RADIUS..=EDGE_RADIUS).any(|dx| is_content(xi + dx, yi + dy)));
+                    if !near_content {
+                        img.put_pixel(x, y, fill);
+                        stripped = true;
+                    }
+                }
+            }
+        }
+    }
+    stripped
+}
+
+#[tauri::command]
+fn set_watermark_removal(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> bool {
+    cfg.strip_watermark.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+#[tauri::command]
+async fn capture_area(x: i32, y: i32, width: u32, height: u32, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    capture_area_checked(x, y, width, height, app, cfg).await.map(|capture| capture.path)
+}
+
+#[derive(serde::Serialize)]
+struct AreaCapture {
+    path: String,
+    requested_width: u32,
+    requested_height: u32,
+    width: u32, // What the backend actually returned, e.g. physical pixels on a scaled display
+    height: u32,
+    resized: bool, // width/height differ from the request
+    scale_factor: f32, // Physical pixels per logical pixel on the captured monitor
+}
+
+// capture_area, also reporting the image's real size; the buffer is always built from that size
+#[tauri::command]
+async fn capture_area_checked(x: i32, y: i32, width: u32, height: u32, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<AreaCapture, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+
+    let image = with_app_windows_hidden(&app, &cfg, retry_blank(&cfg, || screen.capture_area(x, y, width, height).map_err(|e| e.to_string())))
+        .await?;
+
+    let (actual_width, actual_height) = (image.width(), image.height());
+    let path = save_capture(&cfg, actual_width, actual_height, image.into_raw())?;
+    Ok(AreaCapture {
+        path,
+        requested_width: width,
+        requested_height: height,
+        width: actual_width,
+        height: actual_height,
+        resized: (actual_width, actual_height) != (width, height),
+        scale_factor: screen.display_info.scale_factor,
+    })
+}
+
+#[tauri::command]
+async fn capture_full_screen(app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let binding = Screen::all().map_err(|e| e.to_string())?;
+    let screen = binding.first().ok_or("No screens found")?;
+
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+
+    save_capture(&cfg, width, height, image.into_raw())
+}
+
+// Largest RGBA buffer a single capture may allocate (an 8K x 4K screen fits)
+const MAX_CAPTURE_BYTES: u64 = 4 * 8192 * 4608;
+
+fn capture_bytes(width: u32, height: u32) -> u64 {
+    width as u64 * height as u64 * 4
+}
+
+fn physical_size(info: &screenshots::display_info::DisplayInfo) -> (u32, u32) {
+    let scale = info.scale_factor.max(1.0);
+    ((info.width as f32 * scale) as u32, (info.height as f32 * scale) as u32)
+}
+
+// Grab a monitor in full-width horizontal strips that each fit MAX_CAPTURE_BYTES, handing each one
+// to `each` as it arrives. Blank-frame retries are skipped: a single dark strip of a large desktop is normal.
+fn capture_strips(screen: &Screen, mut each: impl FnMut(screenshots::image::RgbaImage) -> Result<(), String>) -> Result<(), String> {
+    let info = screen.display_info;
+    let (physical_width, _) = physical_size(&info);
+    // capture_area works in logical coordinates
+    let strip_rows = ((MAX_CAPTURE_BYTES / capture_bytes(physical_width, 1)) as f32 / info.scale_factor.max(1.0)) as u32;
+    let strip_rows = strip_rows.clamp(1, info.height);
+    for y in (0..info.height).step_by(strip_rows as usize) {
+        let rows = strip_rows.min(info.height - y);
+        each(screen.capture_area(0, y as i32, info.width, rows).map_err(|e| e.to_string())?)?;
+    }
+    Ok(())
+}
+
+// Grab a whole monitor. Screens within MAX_CAPTURE_BYTES are captured directly; larger ones (ultra-wide
+// or virtual desktops) are captured in strips, each scaled down into the result as it arrives, so the
+// full-resolution frame is never held in memory.
+async fn grab_screen(app: &tauri::AppHandle, cfg: &AppConfig, screen: &Screen) -> Result<screenshots::image::RgbaImage, String> {
+    let (physical_width, physical_height) = physical_size(&screen.display_info);
+    if capture_bytes(physical_width, physical_height) <= MAX_CAPTURE_BYTES {
+        return with_app_windows_hidden(app, cfg, retry_blank(cfg, || screen.capture().map_err(|e| e.to_string()))).await;
+    }
+
+    let factor = (MAX_CAPTURE_BYTES as f64 / capture_bytes(physical_width, physical_height) as f64).sqrt();
+    let width = ((physical_width as f64 * factor) as u32).max(1);
+    let height = ((physical_height as f64 * factor) as u32).max(1);
+    let mut canvas = screenshots::image::RgbaImage::new(width, height);
+    // Source rows consumed so far; each strip lands where its rows fall in the scaled frame
+    let mut source_top = 0u32;
+    with_app_windows_hidden(app, cfg, async {
+        capture_strips(screen, |strip| {
+            let top = ((source_top as f64 * factor) as u32).min(height);
+            source_top += strip.height();
+            let bottom = ((source_top as f64 * factor) as u32).min(height);
+            if bottom > top {
+                let scaled = screenshots::image::imageops::resize(&strip, width, bottom - top, screenshots::image::imageops::FilterType::Triangle);
+                screenshots::image::imageops::replace(&mut canvas, &scaled, 0, top as i64);
+            }
+            Ok(())
+        })
+    })
+    .await?;
+    Ok(canvas)
+}
+
+#[derive(serde::Serialize)]
+struct LargeScreenCapture {
+    strategy: &'static str, // "direct", "tiled" or "downscaled"
+    width: u32,
+    height: u32,
+    paths: Vec<String>,
+    queue_length: usize,
+}
+
+// Capture a monitor that may be too large for one image buffer (ultra-wide or virtual desktops).
+// Screens within MAX_CAPTURE_BYTES are captured directly; larger ones are either queued as separate
+// full-resolution strips ("tile") or scaled down like every other full-screen capture ("downscale", the default).
+#[tauri::command]
+async fn capture_large_screen(
+    monitor: Option<usize>,
+    strategy: Option<String>,
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<LargeScreenCapture, String> {
+    let tile = match strategy.as_deref().unwrap_or("downscale") {
+        "tile" => true,
+        "downscale" => false,
+        other => return Err(format!("Unknown strategy '{}'; use tile or downscale", other)),
+    };
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let index = monitor.unwrap_or(0);
+    let screen = screens.get(index).ok_or_else(|| format!("No monitor at index {}", index))?;
+    let (physical_width, physical_height) = physical_size(&screen.display_info);
+    let title = captured_window_title(&app);
+    let fits = capture_bytes(physical_width, physical_height) <= MAX_CAPTURE_BYTES;
+
+    if tile && !fits {
+        // Each strip is written out before the next is grabbed
+        let mut paths = Vec::new();
+        let mut queue_length = 0;
+        with_app_windows_hidden(&app, &cfg, async {
+            capture_strips(screen, |strip| {
+                let (width, height) = (strip.width(), strip.height());
+                let path = save_capture(&cfg, width, height, strip.into_raw())?;
+                queue_length = enqueue_capture(&queue, path.clone(), title.clone());
+                paths.push(path);
+                Ok(())
+            })
+        })
+        .await?;
+        return Ok(LargeScreenCapture { strategy: "tiled", width: physical_width, height: physical_height, paths, queue_length });
+    }
+
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+    let path = save_capture(&cfg, width, height, image.into_raw())?;
+    let queue_length = enqueue_capture(&queue, path.clone(), title);
+    let strategy = if fits { "direct" } else { "downscaled" };
+    Ok(LargeScreenCapture { strategy, width, height, paths: vec![path], queue_length })
+}
+
+// Scale factor of a monitor by index, or of the monitor the overlay is on when omitted
+#[tauri::command]
+fn get_scale_factor(monitor: Option<usize>, app: tauri::AppHandle) -> Result<f32, String> {
+    if monitor.is_none() {
+        if let Some(scale) = app.get_webview_window("main").and_then(|window| window.scale_factor().ok()) {
+            return Ok(scale as f32);
+        }
+    }
+    let index = monitor.unwrap_or(0);
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.get(index).ok_or_else(|| format!("No monitor at index {}", index))?;
+    Ok(screen.display_info.scale_factor)
+}
+
+#[derive(serde::Serialize)]
+struct MonitorInfo {
+    index: usize,
+    id: u32,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    scale_factor: f32,
+    is_primary: bool,
+    mirror_of: Option<usize>, // Index of the canonical display this one duplicates
+}
+
+// A mirrored display reports the same origin and resolution as the one it copies; the first listed is canonical
+fn mirror_of(screens: &[Screen], index: usize) -> Option<usize> {
+    let info = &screens.get(index)?.display_info;
+    screens[..index].iter().position(|other| {
+        let o = &other.display_info;
+        (o.x, o.y, o.width, o.height) == (info.x, info.y, info.width, info.height)
+    })
+}
+
+#[tauri::command]
+fn list_monitors() -> Result<Vec<MonitorInfo>, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    Ok(screens
+        .iter()
+        .enumerate()
+        .map(|(index, screen)| {
+            let info = &screen.display_info;
+            MonitorInfo {
+                index,
+                id: info.id,
+                x: info.x,
+                y: info.y,
+                width: info.width,
+                height: info.height,
+                scale_factor: info.scale_factor,
+                is_primary: info.is_primary,
+                mirror_of: mirror_of(&screens, index),
+            }
+        })
+        .collect())
+}
+
+// Full capture of one monitor; a mirrored index captures its canonical display instead
+#[tauri::command]
+async fn capture_monitor(index: usize, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    if index >= screens.len() {
+        return Err(format!("No monitor at index {}", index));
+    }
+    let screen = &screens[mirror_of(&screens, index).unwrap_or(index)];
+
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+    save_capture(&cfg, width, height, image.into_raw())
+}
+
+// Preview-only capture: PNG data URI built in memory, never written to disk or queued
+#[tauri::command]
+async fn capture_full_screen_bytes(app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+    let raw = image.into_raw();
+
+    run_image_job(&cfg, move || {
+        let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, raw).ok_or("Failed to convert image")?;
+        let mut png = std::io::Cursor::new(Vec::new());
+        buffer.write_to(&mut png, image::ImageFormat::Png).map_err(|e| e.to_string())?;
+        Ok(format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(png.into_inner())))
+    })
+    .await
+}
+
+// Capture a rectangle given as fractions of the primary screen; the requested size is in logical pixels
+#[tauri::command]
+async fn capture_fraction(left: f64, top: f64, right: f64, bottom: f64, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<AreaCapture, String> {
+    let in_range = |v: f64| (0.0..=1.0).contains(&v);
+    if ![left, top, right, bottom].into_iter().all(in_range) {
+        return Err("Fractions must be between 0.0 and 1.0".to_string());
+    }
+    if left >= right || top >= bottom {
+        return Err("Fractions must form a rectangle (left < right, top < bottom)".to_string());
+    }
+
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+    let screen_width = screen.display_info.width as f64;
+    let screen_height = screen.display_info.height as f64;
+
+    let x = (left * screen_width).round() as i32;
+    let y = (top * screen_height).round() as i32;
+    let width = ((right - left) * screen_width).round().max(1.0) as u32;
+    let height = ((bottom - top) * screen_height).round().max(1.0) as u32;
+
+    let image = with_app_windows_hidden(&app, &cfg, retry_blank(&cfg, || screen.capture_area(x, y, width, height).map_err(|e| e.to_string())))
+        .await?;
+
+    let (actual_width, actual_height) = (image.width(), image.height());
+    let path = save_capture(&cfg, actual_width, actual_height, image.into_raw())?;
+    Ok(AreaCapture {
+        path,
+        requested_width: width,
+        requested_height: height,
+        width: actual_width,
+        height: actual_height,
+        resized: (actual_width, actual_height) != (width, height),
+        scale_factor: screen.display_info.scale_factor,
+    })
+}
+
+#[derive(serde::Serialize)]
+struct TextBlockCapture {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    path: String,
+    scale_factor: f32, // Physical pixels per logical pixel on the captured monitor
+    saved_scale: f64, // Saved image pixels per block pixel; below 1.0 when max_capture_dimension shrank it
+}
+
+const INK_CELL: u32 = 8;
+
+// Cells of INK_CELL pixels marked as "ink" (pixels far from the dominant background shade); (grid, cols, rows)
+fn ink_grid(luma: &[u8], width: u32, height: u32) -> Option<(Vec<bool>, u32, u32)> {
+    const CELL: u32 = INK_CELL;
+    const INK_DELTA: i32 = 40;
+    const MIN_INK_PIXELS: u32 = 3;
+
+    let mut histogram = [0u32; 256];
+    for &l in luma {
+        histogram[l as usize] += 1;
+    }
+    let background = histogram.iter().enumerate().max_by_key(|(_, c)| **c)?.0 as i32;
+
+    let (cols, rows) = (width.div_ceil(CELL), height.div_ceil(CELL));
+    let mut ink = vec![false; (cols * rows) as usize];
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut count = 0;
+            for y in row * CELL..((row + 1) * CELL).min(height) {
+                for x in col * CELL..((col + 1) * CELL).min(width) {
+                    if (luma[(y * width + x) as usize] as i32 - background).abs() > INK_DELTA {
+                        count += 1;
+                    }
+                }
+            }
+            ink[(row * cols + col) as usize] = count >= MIN_INK_PIXELS;
+        }
+    }
+    Some((ink, cols, rows))
+}
+
+// Flood fill over ink cells from `seed`, bridging word gaps horizontally and line spacing vertically.
+// Returns the component's cell bounds (min_c, min_r, max_c, max_r) and its number of ink cells.
+fn ink_component(ink: &[bool], cols: u32, rows: u32, seed: (i32, i32), visited: &mut [bool]) -> ((i32, i32, i32, i32), usize) {
+    let is_ink = |c: i32, r: i32| c >= 0 && r >= 0 && c < cols as i32 && r < rows as i32 && ink[(r as u32 * cols + c as u32) as usize];
+    let mut stack = vec![seed];
+    visited[(seed.1 as u32 * cols + seed.0 as u32) as usize] = true;
+    let (mut min_c, mut min_r, mut max_c, mut max_r) = (seed.0, seed.1, seed.0, seed.1);
+    let mut cells = 0;
+    while let Some((c, r)) = stack.pop() {
+        cells += 1;
+        min_c = min_c.min(c);
+        max_c = max_c.max(c);
+        min_r = min_r.min(r);
+        max_r = max_r.max(r);
+        for dr in -2..=2 {
+            for dc in -3..=3 {
+                let (nc, nr) = (c + dc, r + dr);
+                if is_ink(nc, nr) {
+                    let idx = (nr as u32 * cols + nc as u32) as usize;
+                    if !visited[idx] {
+                        visited[idx] = true;
+                        stack.push((nc, nr));
+                    }
+                }
+            }
+        }
+    }
+    ((min_c, min_r, max_c, max_r), cells)
+}
+
+// Pixel rectangle (x, y, w, h) covering cell bounds, with half a cell of margin
+fn cell_bounds_to_pixels((min_c, min_r, max_c, max_r): (i32, i32, i32, i32), width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let margin = INK_CELL / 2;
+    let x0 = (min_c as u32 * INK_CELL).saturating_sub(margin);
+    let y0 = (min_r as u32 * INK_CELL).saturating_sub(margin);
+    let x1 = ((max_c as u32 + 1) * INK_CELL + margin).min(width);
+    let y1 = ((max_r as u32 + 1) * INK_CELL + margin).min(height);
+    (x0, y0, x1 - x0, y1 - y0)
+}
+
+// Find the bounding box (x, y, w, h) of the text block around a point by grouping
+// cells of ink that sit close together.
+fn detect_text_block(luma: &[u8], width: u32, height: u32, px: u32, py: u32) -> Option<(u32, u32, u32, u32)> {
+    const CELL: u32 = INK_CELL;
+    let (ink, cols, rows) = ink_grid(luma, width, height)?;
+
+    // Start from the clicked cell, or the nearest ink cell within a small radius
+    let (seed_col, seed_row) = ((px / CELL).min(cols - 1) as i32, (py / CELL).min(rows - 1) as i32);
+    let is_ink = |c: i32, r: i32| c >= 0 && r >= 0 && c < cols as i32 && r < rows as i32 && ink[(r as u32 * cols + c as u32) as usize];
+    let seed = (0..=6).find_map(|radius: i32| {
+        (-radius..=radius)
+            .flat_map(|dr| (-radius..=radius).map(move |dc| (seed_col + dc, seed_row + dr)))
+            .find(|&(c, r)| is_ink(c, r))
+    })?;
+
+    let mut visited = vec![false; ink.len()];
+    let (cell_bounds, _) = ink_component(&ink, cols, rows, seed, &mut visited);
+    Some(cell_bounds_to_pixels(cell_bounds, width, height))
+}
+
+// Below this share of the screen's ink, the largest block isn't clearly "the" content
+const SMART_CAPTURE_MIN_CONFIDENCE: f32 = 0.6;
+
+// The largest block of ink on screen as (x, y, w, h), with the share of all ink cells it holds (0.0-1.0)
+fn detect_content_bounds(luma: &[u8], width: u32, height: u32) -> Option<((u32, u32, u32, u32), f32)> {
+    let (ink, cols, rows) = ink_grid(luma, width, height)?;
+    let total = ink.iter().filter(|cell| **cell).count();
+    let mut visited = vec![false; ink.len()];
+    let mut best: Option<((i32, i32, i32, i32), usize)> = None;
+    for idx in 0..ink.len() {
+        if !ink[idx] || visited[idx] {
+            continue;
+        }
+        let seed = ((idx as u32 % cols) as i32, (idx as u32 / cols) as i32);
+        let component = ink_component(&ink, cols, rows, seed, &mut visited);
+        if best.is_none_or(|(_, cells)| component.1 > cells) {
+            best = Some(component);
+        }
+    }
+    let (cell_bounds, cells) = best?;
+    Some((cell_bounds_to_pixels(cell_bounds, width, height), cells as f32 / total as f32))
+}
+
+#[derive(serde::Serialize)]
+struct SmartCapture {
+    mode: &'static str, // "region" when cropped to the detected content, "full" otherwise
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    confidence: f32, // Share of the screen's ink inside the detected region; 0 when none was found
+    path: String,
+    queue_length: usize,
+}
+
+// Full-screen capture cropped to the main block of content and queued; falls back to the whole
+// screen when no block clearly dominates or the block already covers nearly all of it
+#[tauri::command]
+async fn smart_capture(app: tauri::AppHandle, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<SmartCapture, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+
+    let title = captured_window_title(&app);
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+    let full = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, image.into_raw())
+        .ok_or("Failed to convert image")?;
+    let luma = image::DynamicImage::ImageRgba8(full.clone()).to_luma8().into_raw();
+
+    let detected = detect_content_bounds(&luma, width, height);
+    let confidence = detected.map_or(0.0, |(_, confidence)| confidence);
+    let region = detected.and_then(|((x, y, w, h), confidence)| {
+        let area_share = (w as f32 * h as f32) / (width as f32 * height as f32);
+        (confidence >= SMART_CAPTURE_MIN_CONFIDENCE && area_share < 0.9).then_some((x, y, w, h))
+    });
+
+    let (mode, (x, y, w, h), raw) = match region {
+        Some((x, y, w, h)) => ("region", (x, y, w, h), image::imageops::crop_imm(&full, x, y, w, h).to_image().into_raw()),
+        None => ("full", (0, 0, width, height), full.into_raw()),
+    };
+    let path = save_capture(&cfg, w, h, raw)?;
+    let queue_length = enqueue_capture(&queue, path.clone(), title);
+    Ok(SmartCapture { mode, x, y, width: w, height: h, confidence, path, queue_length })
+}
+
+#[tauri::command]
+async fn capture_text_block(x: u32, y: u32, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<TextBlockCapture, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+    if x >= width || y >= height {
+        return Err("Point is outside the screen".to_string());
+    }
+
+    let full = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, image.into_raw())
+        .ok_or("Failed to convert image")?;
+    let luma = image::DynamicImage::ImageRgba8(full.clone()).to_luma8().into_raw();
+
+    let (bx, by, bw, bh) = detect_text_block(&luma, width, height, x, y)
+        .ok_or("No text block found near the given point")?;
+    let block = image::imageops::crop_imm(&full, bx, by, bw, bh).to_image();
+    let path = save_capture(&cfg, bw, bh, block.into_raw())?;
+    // The box stays in screen pixels; points in the saved image map back through saved_scale
+    let saved_scale = saved_scale(*cfg.max_capture_dimension.lock().map_err(|_| "Lock poisoned")?, bw, bh);
+
+    Ok(TextBlockCapture { x: bx, y: by, width: bw, height: bh, path, scale_factor: screen.display_info.scale_factor, saved_scale })
+}
+
+#[derive(serde::Serialize)]
+struct WorkAreaCapture {
+    path: String,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    overlay_hidden: bool,
+    scale_factor: f32, // Physical pixels per logical pixel on the captured monitor
+}
+
+// Work area (desktop minus taskbar/docks) of the primary monitor as (x, y, width, height)
+#[cfg(windows)]
+fn primary_work_area(_app: &tauri::AppHandle) -> Result<(i32, i32, u32, u32), String> {
+    use windows::Win32::Foundation::RECT;
+    use windows::Win32::UI::WindowsAndMessaging::{SystemParametersInfoW, SPI_GETWORKAREA, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS};
+
+    let mut rect = RECT::default();
+    unsafe {
+        SystemParametersInfoW(
+            SPI_GETWORKAREA,
+            0,
+            Some(&mut rect as *mut RECT as *mut core::ffi::c_void),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok((rect.left, rect.top, (rect.right - rect.left) as u32, (rect.bottom - rect.top) as u32))
+}
+
+#[cfg(not(windows))]
+fn primary_work_area(app: &tauri::AppHandle) -> Result<(i32, i32, u32, u32), String> {
+    let monitor = app
+        .primary_monitor()
+        .map_err(|e| e.to_string())?
+        .ok_or("No primary monitor found")?;
+    // work_area is physical; capture_area wants logical coordinates
+    let area = monitor.work_area();
+    let position = area.position.to_logical::<i32>(monitor.scale_factor());
+    let size = area.size.to_logical::<u32>(monitor.scale_factor());
+    Ok((position.x, position.y, size.width, size.height))
+}
+
+#[tauri::command]
+async fn capture_work_area(app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<WorkAreaCapture, String> {
+    let (x, y, width, height) = primary_work_area(&app)?;
+
+    // Temporarily hide the overlay if it sits inside the captured area
+    let mut overlay_hidden = false;
+    if let Some(window) = app.get_webview_window("main") {
+        if let (Ok(pos), Ok(size), Ok(true)) = (window.outer_position(), window.outer_size(), window.is_visible()) {
+            let overlaps = pos.x < x + width as i32
+                && pos.x + size.width as i32 > x
+                && pos.y < y + height as i32
+                && pos.y + size.height as i32 > y;
+            if overlaps && window.hide().is_ok() {
+                overlay_hidden = true;
+                // Give the compositor a moment to repaint without the overlay
+                tokio::time::sleep(Duration::from_millis(150)).await;
+            }
+        }
+    }
+
+    let captured = with_app_windows_hidden(&app, &cfg, async {
+        let screens = Screen::all().map_err(|e| e.to_string())?;
+        let screen = screens.first().ok_or("No screens found")?;
+        // capture_area takes coordinates relative to the screen's own origin
+        retry_blank(&cfg, || {
+            screen
+                .capture_area(x - screen.display_info.x, y - screen.display_info.y, width, height)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map(|image| (image, screen.display_info.scale_factor))
+    })
+    .await;
+
+    if overlay_hidden {
+        if let Some(window) = app.get_webview_window("main") {
+            show_window(&app, &window);
+        }
+        reassert_content_protection(&app, expected_protection(&app));
+    }
+
+    let (image, scale_factor) = captured?;
+    // The buffer's own size, which differs from the logical region on scaled displays
+    let path = save_capture(&cfg, image.width(), image.height(), image.into_raw())?;
+    Ok(WorkAreaCapture { path, x, y, width, height, overlay_hidden, scale_factor })
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn save_named_region(
+    name: String,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    monitor: Option<usize>,
+    app: tauri::AppHandle,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err("Region must have a non-zero size".to_string());
+    }
+    // Remember which monitor (index as listed now, default the first) the coordinates belong to
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens
+        .get(monitor.unwrap_or(0))
+        .ok_or_else(|| format!("No monitor at index {}", monitor.unwrap_or(0)))?;
+    let monitor = Some(MonitorKey::of(screen));
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    persisted.named_regions.insert(name, NamedRegion { x, y, width, height, monitor });
+    save_persisted_config(&app, &persisted)
+}
+
+#[tauri::command]
+fn list_named_regions(cfg: tauri::State<'_, AppConfig>) -> Result<HashMap<String, NamedRegion>, String> {
+    Ok(cfg.persisted.lock().map_err(|_| "Lock poisoned")?.named_regions.clone())
+}
+
+#[derive(serde::Serialize)]
+struct NamedRegionCapture {
+    path: String,
+    width: u32,
+    height: u32,
+    scale_factor: f32, // Physical pixels per logical pixel on the captured monitor
+    queue_length: usize,
+}
+
+// Capture a saved region and append it to the queue
+#[tauri::command]
+async fn capture_named_region(name: String, app: tauri::AppHandle, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<NamedRegionCapture, String> {
+    let region = cfg
+        .persisted
+        .lock()
+        .map_err(|_| "Lock poisoned")?
+        .named_regions
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("No region named '{}'", name))?;
+
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let (screen, warning) = find_region_monitor(&screens, region.monitor.as_ref())?;
+    if let Some(message) = warning {
+        let _ = app.emit("region-warning", json!({ "name": name, "message": message }));
+    }
+
+    let title = captured_window_title(&app);
+    let grab = retry_blank(&cfg, || screen.capture_area(region.x, region.y, region.width, region.height).map_err(|e| e.to_string()));
+    let image = with_app_windows_hidden(&app, &cfg, grab).await?;
+    let (width, height) = (image.width(), image.height());
+    let path = save_capture(&cfg, width, height, image.into_raw())?;
+    let queue_length = enqueue_capture(&queue, path.clone(), title);
+    Ok(NamedRegionCapture { path, width, height, scale_factor: screen.display_info.scale_factor, queue_length })
+}
+
+// Run the local Tesseract CLI (override the binary with TESSERACT_CMD) and return the recognized text
+async fn ocr_image(cfg: &AppConfig, image_path: &str) -> Result<String, String> {
+    run_tesseract(cfg, image_path, &[]).await
+}
+
+// The Tesseract CLI (TESSERACT_CMD overrides the binary) as (name, command)
+fn tesseract_command() -> (String, tokio::process::Command) {
+    let tesseract = std::env::var("TESSERACT_CMD").unwrap_or_else(|_| "tesseract".to_string());
+    #[allow(unused_mut)]
+    let mut command = tokio::process::Command::new(&tesseract);
+    #[cfg(windows)]
+    {
+        // Don't flash a console window from the GUI process
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    (tesseract, command)
+}
+
+// Tesseract's stdout for an image in the configured languages, with extra trailing arguments (e.g. the "tsv" config)
+async fn run_tesseract(cfg: &AppConfig, image_path: &str, extra_args: &[&str]) -> Result<String, String> {
+    let languages = cfg.ocr_languages.lock().map_err(|_| "Lock poisoned")?.join("+");
+    let (tesseract, mut command) = tesseract_command();
+    command.arg(image_path).arg("stdout");
+    if !languages.is_empty() {
+        command.arg("-l").arg(languages);
+    }
+    command.args(extra_args);
+
+    let output = command
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run {} (is Tesseract installed?): {}", tesseract, e))?;
+    if !output.status.success() {
+        return Err(format!("OCR failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+#[derive(Clone, serde::Serialize)]
+struct OcrProgress {
+    index: usize, // 1-based position of the image just read
+    total: usize,
+    path: String,
+    text: String, // This image's text
+    text_length: usize, // Characters extracted so far, this image included
+}
+
+// OCR several images in order, emitting "ocr-progress" as each one finishes; pages are joined by newlines
+async fn ocr_images_with_progress(app: &tauri::AppHandle, cfg: &AppConfig, image_paths: &[String]) -> Result<String, String> {
+    let mut text = String::new();
+    for (i, image_path) in image_paths.iter().enumerate() {
+        let page = ocr_image(cfg, image_path).await?;
+        text.push_str(&page);
+        text.push('\n');
+        let _ = app.emit("ocr-progress", OcrProgress {
+            index: i + 1,
+            total: image_paths.len(),
+            path: image_path.clone(),
+            text: page,
+            text_length: text.chars().count(),
+        });
+    }
+    Ok(text)
+}
+
+// OCR the whole queue locally, reporting each image through "ocr-progress"; returns the combined text
+#[tauri::command]
+async fn ocr_queue(app: tauri::AppHandle, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let image_paths = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    if image_paths.is_empty() {
+        return Err("No images in queue".to_string());
+    }
+    let text = ocr_images_with_progress(&app, &cfg, &image_paths).await?;
+    Ok(text.trim_end().to_string())
+}
+
+struct ScoredOcr {
+    text: String,
+    confidence: f32, // Mean word confidence, 0-100, weighted by word length
+}
+
+struct OcrLine<'a> {
+    key: (&'a str, &'a str, &'a str), // (block, paragraph, line)
+    left: u32, // x of the first word, in pixels
+    words: Vec<&'a str>,
+}
+
+// OCR with Tesseract's TSV output, rebuilding lines from the word rows and scoring the result.
+// With `preserve_indent`, leading spaces are rebuilt from where each line starts (see indent_ocr_lines).
+async fn ocr_image_scored(cfg: &AppConfig, image_path: &str, preserve_indent: bool) -> Result<ScoredOcr, String> {
+    let tsv = run_tesseract(cfg, image_path, &["tsv"]).await?;
+    let mut lines: Vec<OcrLine> = Vec::new();
+    let (mut weighted, mut chars) = (0.0f32, 0usize);
+    let mut char_widths = Vec::new();
+    // Columns: level page block par line word left top width height conf text
+    for row in tsv.lines().skip(1) {
+        let cols: Vec<&str> = row.split('\t').collect();
+        if cols.len() < 12 || cols[0] != "5" {
+            continue;
+        }
+        let (word, conf) = (cols[11].trim(), cols[10].parse::<f32>().unwrap_or(-1.0));
+        if word.is_empty() || conf < 0.0 {
+            continue;
+        }
+        let (left, width) = (cols[6].parse::<u32>().unwrap_or(0), cols[8].parse::<u32>().unwrap_or(0));
+        let count = word.chars().count();
+        char_widths.push(width as f32 / count as f32);
+        let key = (cols[2], cols[3], cols[4]);
+        match lines.last_mut() {
+            Some(line) if line.key == key => line.words.push(word),
+            _ => lines.push(OcrLine { key, left, words: vec![word] }),
+        }
+        weighted += conf * count as f32;
+        chars += count;
+    }
+
+    let indents = if preserve_indent { indent_ocr_lines(&lines, &mut char_widths) } else { vec![0; lines.len()] };
+    let mut text = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        match i.checked_sub(1).map(|previous| lines[previous].key.0) {
+            Some(block) if block != line.key.0 => text.push_str("\n\n"),
+            Some(_) => text.push('\n'),
+            None => {}
+        }
+        text.push_str(&" ".repeat(indents[i]));
+        text.push_str(&line.words.join(" "));
+    }
+    let confidence = if chars == 0 { 0.0 } else { weighted / chars as f32 };
+    Ok(ScoredOcr { text, confidence })
+}
+
+// Leading spaces per line, from how far right of the leftmost line it starts, in character widths.
+// Offsets are snapped to levels of the smallest indent seen and written as four spaces per level,
+// so small OCR jitter in x doesn't produce inconsistent (and for Python, invalid) indentation.
+fn indent_ocr_lines(lines: &[OcrLine], char_widths: &mut [f32]) -> Vec<usize> {
+    if lines.is_empty() || char_widths.is_empty() {
+        return vec![0; lines.len()];
+    }
+    char_widths.sort_by(|a, b| a.total_cmp(b));
+    let char_width = char_widths[char_widths.len() / 2].max(1.0);
+    let base = lines.iter().map(|line| line.left).min().unwrap_or(0);
+    let columns: Vec<f32> = lines.iter().map(|line| (line.left - base) as f32 / char_width).collect();
+    // Under one character is the same column
+    let unit = columns.iter().copied().filter(|c| *c >= 1.0).fold(f32::INFINITY, f32::min);
+    if !unit.is_finite() {
+        return vec![0; lines.len()];
+    }
+    columns.iter().map(|c| (c / unit).round() as usize * 4).collect()
+}
+
+// Language packs the local Tesseract has installed
+async fn installed_ocr_languages() -> Result<Vec<String>, String> {
+    let (tesseract, mut command) = tesseract_command();
+    let output = command
+        .arg("--list-langs")
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run {} (is Tesseract installed?): {}", tesseract, e))?;
+    // The first line is a header ("List of available languages in ...")
+    Ok(String::from_utf8_lossy(&output.stdout).lines().skip(1).map(|line| line.trim().to_string()).filter(|l| !l.is_empty()).collect())
+}
+
+// Tesseract language packs used by every OCR path, e.g. ["eng", "fra"]; an empty list restores Tesseract's default
+#[tauri::command]
+async fn set_ocr_languages(langs: Vec<String>, cfg: tauri::State<'_, AppConfig>) -> Result<Vec<String>, String> {
+    let langs: Vec<String> = langs.iter().map(|lang| lang.trim().to_string()).filter(|lang| !lang.is_empty()).collect();
+    if let Some(bad) = langs.iter().find(|lang| !lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '/')) {
+        return Err(format!("'{}' is not a Tesseract language code", bad));
+    }
+    if !langs.is_empty() {
+        let installed = installed_ocr_languages().await?;
+        let missing: Vec<&str> = langs.iter().filter(|lang| !installed.contains(lang)).map(String::as_str).collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Tesseract language pack(s) not installed: {} (installed: {})",
+                missing.join(", "),
+                installed.join(", ")
+            ));
+        }
+    }
+    *cfg.ocr_languages.lock().map_err(|_| "Lock poisoned")? = langs.clone();
+    Ok(langs)
+}
+
+// Rebuild leading indentation in OCR text from each line's position (for Python and other indented code)
+#[tauri::command]
+fn set_ocr_preserve_indent(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> bool {
+    cfg.ocr_preserve_indent.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+// Render LaTeX to a PNG with the local TeX tools (override with LATEX_CMD / DVIPNG_CMD) so extracted
+// formulas can be checked by eye. Bare formulas are typeset as display math; returns the image path.
+#[tauri::command]
+async fn render_latex(source: String, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let source = source.trim();
+    if source.is_empty() {
+        return Err("Nothing to render".to_string());
+    }
+    // Only formulas are expected; refuse primitives that read or write other files
+    for primitive in ["\\input", "\\include", "\\openin", "\\openout", "\\write18", "\\read"] {
+        if source.contains(primitive) {
+            return Err(format!("{} is not allowed in rendered LaTeX", primitive));
+        }
+    }
+    let body = if ["$", "\\[", "\\(", "\\begin{"].iter().any(|marker| source.contains(marker)) {
+        source.to_string()
+    } else {
+        format!("\\[{}\\]", source)
+    };
+    let document = format!(
+        "\\documentclass{{article}}\n\\usepackage{{amsmath,amssymb}}\n\\pagestyle{{empty}}\n\\begin{{document}}\n{}\n\\end{{document}}\n",
+        body
+    );
+
+    let out_dir = capture_dir(&cfg);
+    let work_dir = out_dir.join(format!("latex_{}", Uuid::new_v4()));
+    fs::create_dir_all(&work_dir).map_err(|e| e.to_string())?;
+    let output = out_dir.join(format!("{}_latex.png", Uuid::new_v4()));
+
+    let result = async {
+        fs::write(work_dir.join("formula.tex"), document).map_err(|e| e.to_string())?;
+        let latex = std::env::var("LATEX_CMD").unwrap_or_else(|_| "latex".to_string());
+        let dvipng = std::env::var("DVIPNG_CMD").unwrap_or_else(|_| "dvipng".to_string());
+        let mut typeset = tokio::process::Command::new(&latex);
+        typeset
+            .args(["-interaction=nonstopmode", "-halt-on-error", "-no-shell-escape", "formula.tex"])
+            .current_dir(&work_dir)
+            .kill_on_drop(true); // A timed-out run is killed rather than left behind
+        let mut convert = tokio::process::Command::new(&dvipng);
+        convert
+            .args(["-D", "200", "-T", "tight", "-bg", "White", "-o"])
+            .arg(&output)
+            .arg("formula.dvi")
+            .current_dir(&work_dir)
+            .kill_on_drop(true);
+
+        for (program, command) in [(&latex, &mut typeset), (&dvipng, &mut convert)] {
+            #[cfg(windows)]
+            {
+                // Don't flash a console window from the GUI process
+                const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+                command.creation_flags(CREATE_NO_WINDOW);
+            }
+            let run = tokio::time::timeout(Duration::from_secs(20), command.output())
+                .await
+                .map_err(|_| format!("{} timed out", program))?
+                .map_err(|e| format!("Failed to run {} (is a TeX distribution installed?): {}", program, e))?;
+            if !run.status.success() {
+                // TeX reports errors on stdout as lines starting with "!"
+                let log = String::from_utf8_lossy(&run.stdout);
+                let error = log
+                    .lines()
+                    .find(|line| line.starts_with('!'))
+                    .map(str::to_string)
+                    .unwrap_or_else(|| String::from_utf8_lossy(&run.stderr).trim().to_string());
+                return Err(format!("{} failed: {}", program, error));
+            }
+        }
+        Ok(output.to_string_lossy().to_string())
+    }
+    .await;
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+#[tauri::command]
+async fn capture_area_ocr_to_clipboard(
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    app: tauri::AppHandle,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<String, String> {
+    let path = capture_area(x, y, width, height, app.clone(), cfg.clone()).await?;
+    let text = ocr_image(&cfg, &path).await?;
+    app.clipboard().write_text(text.clone()).map_err(|e| e.to_string())?;
+    Ok(text)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ScreenContext {
+    path: String,
+    text: String,
+}
+
+// Capture a region (e.g. a problem's constraints) as standing system context for following calls.
+// Gemini system messages take text only, so the region travels as its OCR text; it stays until cleared or replaced.
+#[tauri::command]
+async fn capture_area_as_context(
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    app: tauri::AppHandle,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<ScreenContext, String> {
+    let path = capture_area(x, y, width, height, app, cfg.clone()).await?;
+    let text = ocr_image(&cfg, &path).await?;
+    let text = text.trim().to_string();
+    if text.is_empty() {
+        return Err("No text found in the context region".to_string());
+    }
+    let context = ScreenContext { path, text };
+    *cfg.screen_context.lock().map_err(|_| "Lock poisoned")? = Some(context.clone());
+    Ok(context)
+}
+
+// Stop sending the region from capture_area_as_context; returns whether one was set
+#[tauri::command]
+fn clear_screen_context(cfg: tauri::State<'_, AppConfig>) -> Result<bool, String> {
+    Ok(cfg.screen_context.lock().map_err(|_| "Lock poisoned")?.take().is_some())
+}
+
+// Downscale captures whose longest side exceeds `max_dimension`; 0 keeps captures at full resolution
+#[tauri::command]
+fn set_max_capture_dimension(max_dimension: u32, cfg: tauri::State<'_, AppConfig>) -> Result<Option<u32>, String> {
+    let value = (max_dimension > 0).then_some(max_dimension);
+    *cfg.max_capture_dimension.lock().map_err(|_| "Lock poisoned")? = value;
+    Ok(value)
+}
+
+#[tauri::command]
+fn set_capture_enhancement(strength: Option<f32>, cfg: tauri::State<'_, AppConfig>) -> Result<Option<f32>, String> {
+    if let Some(v) = strength {
+        if !(0.0..=1.0).contains(&v) {
+            return Err("Enhancement strength must be between 0.0 and 1.0".to_string());
+        }
+    }
+    *cfg.capture_enhance.lock().map_err(|_| "Lock poisoned")? = strength;
+    Ok(strength)
+}
+
+#[tauri::command]
+fn set_gemini_api_key(key: String, cfg: tauri::State<'_, AppConfig>) -> Result<(), String> {
+    // Store in memory and set environment for underlying client
+    {
+        let mut api_key_guard = cfg.api_key.lock().map_err(|_| "Lock poisoned")?;
+        *api_key_guard = if key.trim().is_empty() { None } else { Some(key.clone()) };
+    }
+    if key.trim().is_empty() {
+        std::env::remove_var("GEMINI_API_KEY");
+    } else {
+        std::env::set_var("GEMINI_API_KEY", key);
+    }
+
+    // Persist to Windows user environment and broadcast change
+    #[cfg(windows)]
+    {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        if let Ok(env) = hkcu.open_subkey_with_flags("Environment", winreg::enums::KEY_SET_VALUE) {
+            if let Err(e) = env.set_value("GEMINI_API_KEY", &std::env::var("GEMINI_API_KEY").unwrap_or_default()) {
+                eprintln!("Failed to write GEMINI_API_KEY to registry: {}", e);
+            }
+        }
+
+        unsafe {
+            let _ = SendMessageTimeoutW(
+                HWND_BROADCAST,
+                WM_SETTINGCHANGE,
+                WPARAM(0),
+                LPARAM(w!("Environment").as_ptr() as isize),
+                SMTO_ABORTIFHUNG,
+                5000,
+                None,
+            );
+        }
+    }
+    Ok(())
 }
 
 #[tauri::command]
 fn get_gemini_api_key(cfg: tauri::State<'_, AppConfig>) -> Option<String> {
     // First try to get from environment variable
-    if let Ok(key) = std::env::var("GEMINI_API_KEY") {
-        if !key.trim().is_empty() {
+    if let Ok(key) = std::env::var("GEMINI_API_KEY") {
+        if !key.trim().is_empty() {
+            // Update AppConfig to match environment variable
+            if let Ok(mut guard) = cfg.api_key.lock() {
+                *guard = Some(key.clone());
+            }
+            return Some(key);
+        }
+    }
+    
+    // If not in environment, try AppConfig
+    if let Ok(guard) = cfg.api_key.lock() {
+        if let Some(key) = guard.clone() {
+            return Some(key);
+        }
+    }
+    
+    None
+}
+
+#[tauri::command]
+fn set_model(model: String, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let mut model_guard = cfg.model.lock().map_err(|_| "Lock poisoned")?;
+    *model_guard = model.clone();
+    cfg.model_explicit.store(true, Ordering::SeqCst);
+    Ok(model)
+}
+
+#[tauri::command]
+fn set_gemini_endpoint(
+    endpoint: Option<String>,
+    allow_custom_endpoint: Option<bool>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<(), String> {
+    let endpoint = endpoint.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+    if let Some(ref e) = endpoint {
+        // Validate the URL up front; the allowlist itself is enforced on every call
+        reqwest::Url::parse(e).map_err(|err| format!("Invalid endpoint URL: {}", err))?;
+    }
+    *cfg.gemini_endpoint.lock().map_err(|_| "Lock poisoned")? = endpoint;
+    cfg.allow_custom_endpoint
+        .store(allow_custom_endpoint.unwrap_or(false), Ordering::SeqCst);
+    Ok(())
+}
+
+#[tauri::command]
+fn set_endpoint_allowlist(hosts: Vec<String>, cfg: tauri::State<'_, AppConfig>) -> Result<Vec<String>, String> {
+    let hosts: Vec<String> = hosts
+        .into_iter()
+        .map(|h| h.trim().to_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect();
+    *cfg.endpoint_allowlist.lock().map_err(|_| "Lock poisoned")? = hosts.clone();
+    Ok(hosts)
+}
+
+// Accepts a locale like "es-ES"; "auto" uses the OS locale and "" or "en" means English (None)
+fn resolve_locale(locale: &str) -> Option<String> {
+    match locale.trim() {
+        "auto" => sys_locale::get_locale(),
+        other => Some(other.to_string()),
+    }
+    .filter(|l| !l.is_empty() && !l.to_lowercase().starts_with("en"))
+}
+
+#[tauri::command]
+fn set_response_locale(locale: String, cfg: tauri::State<'_, AppConfig>) -> Result<Option<String>, String> {
+    let locale = resolve_locale(&locale);
+    *cfg.response_locale.lock().map_err(|_| "Lock poisoned")? = locale.clone();
+    Ok(locale)
+}
+
+// 0 removes the limit
+#[tauri::command]
+fn set_answer_length(max_words: u32, cfg: tauri::State<'_, AppConfig>) -> Result<Option<u32>, String> {
+    let limit = if max_words == 0 { None } else { Some(max_words) };
+    *cfg.answer_max_words.lock().map_err(|_| "Lock poisoned")? = limit;
+    Ok(limit)
+}
+
+// An empty template removes it
+#[tauri::command]
+fn set_answer_template(template: String, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<(), String> {
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    persisted.answer_template = (!template.trim().is_empty()).then_some(template);
+    save_persisted_config(&app, &persisted)
+}
+
+// Template lines the answer failed to reproduce; empty when it follows the template (or none is set)
+#[tauri::command]
+fn validate_answer_structure(answer: String, cfg: tauri::State<'_, AppConfig>) -> Result<Vec<String>, String> {
+    let persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    Ok(persisted
+        .answer_template
+        .as_deref()
+        .map(|template| missing_template_lines(template, &answer))
+        .unwrap_or_default())
+}
+
+// Extra system instructions appended for every model of a provider; an empty prompt removes them
+#[tauri::command]
+fn set_system_prompt_for(provider: String, prompt: String, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<(), String> {
+    let provider = provider.trim().to_lowercase();
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    if prompt.trim().is_empty() {
+        persisted.provider_system_prompts.remove(&provider);
+    } else {
+        persisted.provider_system_prompts.insert(provider, prompt);
+    }
+    save_persisted_config(&app, &persisted)
+}
+
+const KEYCHAIN_SERVICE: &str = "interview-helper";
+
+fn profile_secret(profile: &str, secret: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, &format!("profile/{}/{}", profile, secret)).map_err(|e| e.to_string())
+}
+
+// Store a profile secret in the keychain; None removes any stale entry
+fn store_profile_secret(profile: &str, secret: &str, value: Option<&str>) -> Result<(), String> {
+    let entry = profile_secret(profile, secret)?;
+    match value {
+        Some(value) => entry.set_password(value).map_err(|e| e.to_string()),
+        None => match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        },
+    }
+}
+
+fn load_profile_secret(profile: &str, secret: &str) -> Result<Option<String>, String> {
+    match profile_secret(profile, secret)?.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+fn save_profile(name: String, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<ProviderProfile, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Profile name cannot be empty".to_string());
+    }
+    let model = cfg.model.lock().map_err(|_| "Lock poisoned")?.clone();
+    let profile = ProviderProfile {
+        provider: model_provider(&model),
+        model,
+        endpoint: cfg.gemini_endpoint.lock().map_err(|_| "Lock poisoned")?.clone(),
+        allow_custom_endpoint: cfg.allow_custom_endpoint.load(Ordering::SeqCst),
+    };
+
+    let api_key = cfg.api_key.lock().map_err(|_| "Lock poisoned")?.clone();
+    let hf_token = cfg.hf_token.lock().map_err(|_| "Lock poisoned")?.clone();
+    store_profile_secret(&name, "gemini_api_key", api_key.as_deref())?;
+    store_profile_secret(&name, "hf_token", hf_token.as_deref())?;
+
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    persisted.profiles.insert(name.clone(), profile.clone());
+    persisted.active_profile = Some(name);
+    save_persisted_config(&app, &persisted)?;
+    Ok(profile)
+}
+
+#[tauri::command]
+fn activate_profile(name: String, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<ProviderProfile, String> {
+    let profile = cfg
+        .persisted
+        .lock()
+        .map_err(|_| "Lock poisoned")?
+        .profiles
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("No profile named '{}'", name))?;
+    let api_key = load_profile_secret(&name, "gemini_api_key")?;
+    let hf_token = load_profile_secret(&name, "hf_token")?;
+
+    *cfg.model.lock().map_err(|_| "Lock poisoned")? = profile.model.clone();
+    cfg.model_explicit.store(true, Ordering::SeqCst);
+    *cfg.gemini_endpoint.lock().map_err(|_| "Lock poisoned")? = profile.endpoint.clone();
+    cfg.allow_custom_endpoint.store(profile.allow_custom_endpoint, Ordering::SeqCst);
+    // Keychain secrets stay out of the registry: only memory and this process's environment change,
+    // and a secret the profile doesn't define leaves the current one in place
+    if let Some(key) = api_key.filter(|key| !key.trim().is_empty()) {
+        std::env::set_var("GEMINI_API_KEY", &key);
+        *cfg.api_key.lock().map_err(|_| "Lock poisoned")? = Some(key);
+    }
+    if let Some(token) = hf_token.filter(|token| !token.trim().is_empty()) {
+        std::env::set_var("HUGGINGFACE_TOKEN", &token);
+        *cfg.hf_token.lock().map_err(|_| "Lock poisoned")? = Some(token);
+    }
+
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    persisted.active_profile = Some(name);
+    save_persisted_config(&app, &persisted)?;
+    Ok(profile)
+}
+
+#[tauri::command]
+fn list_profiles(cfg: tauri::State<'_, AppConfig>) -> Result<HashMap<String, ProviderProfile>, String> {
+    Ok(cfg.persisted.lock().map_err(|_| "Lock poisoned")?.profiles.clone())
+}
+
+#[tauri::command]
+fn set_hf_token(token: String, cfg: tauri::State<'_, AppConfig>) -> Result<(), String> {
+    // First, check if the environment variable has changed
+    let _env_token = std::env::var("HUGGINGFACE_TOKEN").ok();
+    let mut hf_token_guard = cfg.hf_token.lock().map_err(|_| "Lock poisoned")?;
+    
+    // Check if the provided token is different from what's in AppConfig
+    let current_token = hf_token_guard.clone();
+    let token_changed = current_token != Some(token.clone());
+    
+    // If token is empty, clear everything
+    if token.trim().is_empty() {
+        *hf_token_guard = None;
+        std::env::remove_var("HUGGINGFACE_TOKEN");
+    } else {
+        // Update AppConfig with new token
+        *hf_token_guard = Some(token.clone());
+        
+        // Only update environment variable if token has changed
+        if token_changed {
+            std::env::set_var("HUGGINGFACE_TOKEN", token.clone());
+        }
+    }
+
+    // Persist to Windows user environment and broadcast change
+    #[cfg(windows)]
+    {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        if let Ok(env) = hkcu.open_subkey_with_flags("Environment", winreg::enums::KEY_SET_VALUE) {
+            let token_value = std::env::var("HUGGINGFACE_TOKEN").unwrap_or_default();
+            println!("DEBUG: Writing HUGGINGFACE_TOKEN to registry: {}", &token_value[..std::cmp::min(10, token_value.len())]);
+            if let Err(e) = env.set_value("HUGGINGFACE_TOKEN", &token_value) {
+                eprintln!("Failed to write HUGGINGFACE_TOKEN to registry: {}", e);
+            } else {
+                println!("DEBUG: Successfully wrote HUGGINGFACE_TOKEN to registry");
+            }
+        } else {
+            println!("DEBUG: Failed to open registry key for HUGGINGFACE_TOKEN");
+        }
+
+        unsafe {
+            let _ = SendMessageTimeoutW(
+                HWND_BROADCAST,
+                WM_SETTINGCHANGE,
+                WPARAM(0),
+                LPARAM(w!("Environment").as_ptr() as isize),
+                SMTO_ABORTIFHUNG,
+                5000,
+                None,
+            );
+        }
+        println!("DEBUG: Broadcasted environment change");
+    }
+    Ok(())
+}
+
+fn hf_header_map(headers: &HashMap<String, String>) -> Result<reqwest::header::HeaderMap, String> {
+    let mut map = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        let name = reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name: {}", name))?;
+        let value = reqwest::header::HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("Invalid value for header {}", name))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+#[tauri::command]
+fn set_hf_headers(headers: HashMap<String, String>, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<HashMap<String, String>, String> {
+    hf_header_map(&headers)?;
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    persisted.hf_headers = headers.clone();
+    save_persisted_config(&app, &persisted)?;
+    Ok(headers)
+}
+
+#[tauri::command]
+fn get_hf_headers(cfg: tauri::State<'_, AppConfig>) -> Result<HashMap<String, String>, String> {
+    Ok(cfg.persisted.lock().map_err(|_| "Lock poisoned")?.hf_headers.clone())
+}
+
+// The last Beast Mode HF reply exactly as received, for when its answer wasn't picked up
+#[tauri::command]
+fn get_last_hf_raw(cfg: tauri::State<'_, AppConfig>) -> Result<Option<HfRawResponse>, String> {
+    Ok(cfg.last_hf_raw.lock().map_err(|_| "Lock poisoned")?.clone())
+}
+
+#[tauri::command]
+fn get_hf_token(cfg: tauri::State<'_, AppConfig>) -> Option<String> {
+    // First try to get from environment variable
+    if let Ok(token) = std::env::var("HUGGINGFACE_TOKEN") {
+        if !token.trim().is_empty() {
+            println!("DEBUG: Found HUGGINGFACE_TOKEN in environment: {}", &token[..std::cmp::min(10, token.len())]);
             // Update AppConfig to match environment variable
-            if let Ok(mut guard) = cfg.api_key.lock() {
-                *guard = Some(key.clone());
+            if let Ok(mut guard) = cfg.hf_token.lock() {
+                *guard = Some(token.clone());
+            }
+            return Some(token);
+        } else {
+            println!("DEBUG: HUGGINGFACE_TOKEN is empty");
+        }
+    } else {
+        println!("DEBUG: HUGGINGFACE_TOKEN not found in environment");
+    }
+    
+    // If not in environment, try AppConfig
+    if let Ok(guard) = cfg.hf_token.lock() {
+        if let Some(token) = guard.clone() {
+            println!("DEBUG: Found HUGGINGFACE_TOKEN in AppConfig: {}", &token[..std::cmp::min(10, token.len())]);
+            return Some(token);
+        } else {
+            println!("DEBUG: No HUGGINGFACE_TOKEN in AppConfig");
+        }
+    }
+    
+    println!("DEBUG: No HUGGINGFACE_TOKEN found anywhere");
+    None
+}
+
+// Claim one of the concurrent AI request slots; extra requests are rejected rather than piling up.
+// Every AI call passes through here, so this is also where meeting lockdown is enforced.
+fn request_slot(cfg: &AppConfig) -> Result<tokio::sync::OwnedSemaphorePermit, String> {
+    if cfg.meeting_lockdown.load(Ordering::SeqCst) && !cfg.meeting_lockdown_override.swap(false, Ordering::SeqCst) {
+        if let Some(title) = foreground_meeting_window() {
+            return Err(format!("Locked: meeting active (\"{}\"). Override to send anyway.", title));
+        }
+    }
+    let (limit, slots) = cfg.request_slots.lock().map_err(|_| "Lock poisoned")?.clone();
+    slots.try_acquire_owned().map_err(|_| {
+        format!("Busy: {} concurrent AI request(s) already running. Wait for it to finish or raise the limit.", limit)
+    })
+}
+
+// Requests already running keep their slots on the old semaphore until they finish
+#[tauri::command]
+fn set_max_concurrent_requests(limit: usize, cfg: tauri::State<'_, AppConfig>) -> Result<usize, String> {
+    let limit = limit.max(1);
+    *cfg.request_slots.lock().map_err(|_| "Lock poisoned")? = (limit, Arc::new(tokio::sync::Semaphore::new(limit)));
+    Ok(limit)
+}
+
+#[tauri::command]
+async fn call_gemini(prompt: String, cfg: tauri::State<'_, AppConfig>, conv: tauri::State<'_, Conversation>) -> Result<String, String> {
+    let _slot = request_slot(&cfg)?;
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+
+    let client = gemini_client(&cfg)?;
+
+    let system = "Be concise and helpful.";
+    let model = model_for_request(&cfg, &prompt, &[])?;
+
+    let chat_req = ChatRequest::new(vec![
+        ChatMessage::system(system_prompt(&cfg, &model, system)),
+        ChatMessage::user(&prompt),
+    ]);
+
+    let res = exec_chat(&cfg, &client, &model, chat_req.clone(), None)
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+    remember_response_meta(&conv, &res);
+
+    let answer = res
+        .content_text_as_str()
+        .unwrap_or("[No response]")
+        .to_string();
+    let answer = continue_if_truncated(&client, &model, chat_req, answer, &cfg, &conv).await;
+    record_exchange(&cfg, &conv, &prompt, &answer);
+    remember_request(&conv, RecordedRequest {
+        mode: "text".to_string(),
+        system: system.to_string(),
+        prompt,
+        image_paths: Vec::new(),
+        model,
+        answer: answer.clone(),
+    });
+    Ok(answer)
+}
+
+// 0 picks half the cores so image work never saturates the machine mid-interview
+fn build_image_pool(threads: usize) -> Result<rayon::ThreadPool, String> {
+    let threads = if threads == 0 {
+        std::thread::available_parallelism().map(|n| (n.get() / 2).max(1)).unwrap_or(2)
+    } else {
+        threads
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("image-worker-{}", i))
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// Run CPU-heavy image work on the bounded pool instead of the async executor threads
+async fn run_image_job<T, F>(cfg: &AppConfig, job: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    let pool = cfg.image_pool.lock().map_err(|_| "Lock poisoned")?.clone();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    pool.spawn(move || {
+        let _ = tx.send(job());
+    });
+    rx.await.map_err(|_| "Image job was dropped".to_string())?
+}
+
+async fn encode_image(cfg: &AppConfig, image_path: &str) -> Result<ContentPart, String> {
+    if let Some(url) = served_image_url(cfg, image_path)? {
+        return Ok(ContentPart::from_image_url("image/png", url));
+    }
+    let path = image_path.to_string();
+    run_image_job(cfg, move || image_part(&path)).await
+}
+
+// URL for a capture on the local server, when it runs and the endpoint is local enough to reach it.
+// Cloud endpoints can't fetch from localhost, so they always get base64.
+fn served_image_url(cfg: &AppConfig, image_path: &str) -> Result<Option<String>, String> {
+    let Some(port) = *cfg.image_server_port.lock().map_err(|_| "Lock poisoned")? else {
+        return Ok(None);
+    };
+    let endpoint = cfg.gemini_endpoint.lock().map_err(|_| "Lock poisoned")?.clone();
+    let local = endpoint
+        .and_then(|e| reqwest::Url::parse(&e).ok())
+        .and_then(|url| url.host_str().map(|host| matches!(host, "localhost" | "127.0.0.1" | "[::1]")))
+        .unwrap_or(false);
+    let path = std::path::Path::new(image_path);
+    let served = path.parent() == Some(capture_dir(cfg).as_path());
+    Ok((local && served).then(|| format!("http://127.0.0.1:{}/{}", port, image_name(image_path))))
+}
+
+// Answer one GET for a capture by file name; anything else is a 404
+async fn serve_capture(mut stream: tokio::net::TcpStream, dir: PathBuf) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut buf = [0u8; 4096];
+    let Ok(n) = stream.read(&mut buf).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let name = request
+        .strip_prefix("GET /")
+        .and_then(|rest| rest.split_whitespace().next())
+        .filter(|name| name.ends_with(".png") && !name.contains(['/', '\\']) && !name.contains(".."));
+    let body = match name {
+        Some(name) => tokio::fs::read(dir.join(name)).await.ok(),
+        None => None,
+    };
+
+    let response = match body {
+        Some(body) => {
+            let mut response = format!("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+            response.extend(body);
+            response
+        }
+        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+    };
+    let _ = stream.write_all(&response).await;
+}
+
+// Serve captures on 127.0.0.1 so sends to a local endpoint can reference them by URL; port 0 picks a free one
+#[tauri::command]
+async fn start_image_server(
+    port: u16,
+    app: tauri::AppHandle,
+    cfg: tauri::State<'_, AppConfig>,
+    tasks: tauri::State<'_, BackgroundTasks>,
+) -> Result<u16, String> {
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await.map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    let handle = tauri::async_runtime::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            // Looked up per request so a changed temp prefix takes effect
+            let dir = capture_dir(&app.state::<AppConfig>());
+            tokio::spawn(serve_capture(stream, dir));
+        }
+    });
+    tasks.track("image-server", handle);
+    *cfg.image_server_port.lock().map_err(|_| "Lock poisoned")? = Some(port);
+    Ok(port)
+}
+
+#[tauri::command]
+fn stop_image_server(cfg: tauri::State<'_, AppConfig>, tasks: tauri::State<'_, BackgroundTasks>) -> Result<bool, String> {
+    *cfg.image_server_port.lock().map_err(|_| "Lock poisoned")? = None;
+    Ok(tasks.stop("image-server"))
+}
+
+#[tauri::command]
+fn set_image_threads(threads: usize, cfg: tauri::State<'_, AppConfig>) -> Result<usize, String> {
+    let pool = build_image_pool(threads)?;
+    let count = pool.current_num_threads();
+    // Jobs already queued on the old pool finish there before it is dropped
+    *cfg.image_pool.lock().map_err(|_| "Lock poisoned")? = Arc::new(pool);
+    Ok(count)
+}
+
+// Read a PNG from disk and wrap it as an inline base64 image part
+fn image_part(image_path: &str) -> Result<ContentPart, String> {
+    let mut file = File::open(image_path).map_err(|e| e.to_string())?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).map_err(|e| e.to_string())?;
+
+    let encoded_image = general_purpose::STANDARD.encode(&buffer);
+    Ok(ContentPart::from_image_base64("image/png", Arc::from(encoded_image)))
+}
+
+// Fully decode an image so truncated or corrupt files are caught before upload
+fn validate_image(image_path: &str) -> Result<(), String> {
+    image::ImageReader::open(image_path)
+        .map_err(|e| e.to_string())?
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .decode()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn image_name(image_path: &str) -> String {
+    std::path::Path::new(image_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| image_path.to_string())
+}
+
+#[tauri::command]
+async fn call_gemini_with_image(prompt: String, image_path: String, cfg: tauri::State<'_, AppConfig>, conv: tauri::State<'_, Conversation>) -> Result<String, String> {
+    let _slot = request_slot(&cfg)?;
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+    ensure_no_video_call(&cfg)?;
+
+    let model = model_for_request(&cfg, &prompt, std::slice::from_ref(&image_path))?;
+    let image = image_content(&cfg, &image_path, model_supports_vision(&model)).await?;
+
+    let client = gemini_client(&cfg)?;
+    let system = "Be concise and helpful.";
+
+    let chat_req = ChatRequest::new(vec![
+        ChatMessage::system(system_prompt(&cfg, &model, system)),
+        ChatMessage::user(order_parts(&cfg, ContentPart::from_text(prompt.clone()), vec![image])),
+    ]);
+
+    let res = exec_chat(&cfg, &client, &model, chat_req.clone(), None)
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+    remember_response_meta(&conv, &res);
+
+    let answer = res
+        .content_text_as_str()
+        .unwrap_or("[No response]")
+        .to_string();
+    let answer = continue_if_truncated(&client, &model, chat_req, answer, &cfg, &conv).await;
+    record_exchange(&cfg, &conv, &prompt, &answer);
+    remember_request(&conv, RecordedRequest {
+        mode: "image".to_string(),
+        system: system.to_string(),
+        prompt,
+        image_paths: vec![image_path],
+        model,
+        answer: answer.clone(),
+    });
+    Ok(answer)
+}
+
+// Keywords that OCR commonly mangles by swapping letters for look-alike digits
+const OCR_CODE_KEYWORDS: &[&str] = &[
+    "bool", "break", "class", "const", "continue", "def", "double", "elif", "else", "false", "float", "for",
+    "func", "if", "import", "include", "int", "lambda", "len", "let", "list", "long", "new", "nil", "none",
+    "null", "nullptr", "print", "println", "public", "return", "self", "sizeof", "static", "std", "string",
+    "throw", "true", "void", "while",
+];
+
+fn looks_like_code(line: &str) -> bool {
+    line.chars().any(|c| ";{}()[]=<>".contains(c))
+}
+
+// `identifier_context` is true when the token follows a `.` or precedes a `(`, so it can't be a number
+fn fix_ocr_token(token: &str, identifier_context: bool) -> String {
+    let starts_with_digit = token.chars().next().is_some_and(|c| c.is_ascii_digit());
+    let is_numeric_like = token.chars().all(|c| c.is_ascii_digit() || "OolI".contains(c));
+
+    // Numeric literal with letters mixed in: "1O0" -> "100", "2l5" -> "215".
+    // A trailing l is left alone since it may be a C/C++ long suffix.
+    if starts_with_digit && is_numeric_like && !token.contains("0x") {
+        let last = token.len() - 1;
+        return token
+            .char_indices()
+            .map(|(i, c)| match c {
+                'O' | 'o' => '0',
+                'l' | 'I' if i != last => '1',
+                _ => c,
+            })
+            .collect();
+    }
+
+    // Identifier with digits in it that becomes a known keyword: "f0r" -> "for", "wh1le" -> "while".
+    // A leading digit usually means a literal ("1f", "1e5"), so those are only rewritten in identifier position.
+    if (!starts_with_digit || identifier_context)
+        && token.chars().any(|c| c == '0' || c == '1')
+        && token.chars().any(|c| c.is_ascii_alphabetic())
+    {
+        let lowered: String = token.chars().map(|c| if c == '0' { 'o' } else { c }).collect();
+        for candidate in [lowered.replace('1', "l"), lowered.replace('1', "i")] {
+            if OCR_CODE_KEYWORDS.contains(&candidate.as_str()) {
+                return candidate;
+            }
+        }
+    }
+
+    token.to_string()
+}
+
+/// Conservative cleanup of OCR output: only touches lines that look like code,
+/// and only rewrites tokens whose intended form is unambiguous.
+fn cleanup_ocr_text(text: &str) -> String {
+    let mut cleaned = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            cleaned.push('\n');
+        }
+        if !looks_like_code(line) {
+            cleaned.push_str(line);
+            continue;
+        }
+        let chars: Vec<char> = line.chars().collect();
+        let mut token = String::new();
+        let mut before = None; // Character just before the current token
+        for (j, &c) in chars.iter().enumerate() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                token.push(c);
+                continue;
+            }
+            if !token.is_empty() {
+                let called = chars[j..].iter().find(|c| !c.is_whitespace()) == Some(&'(');
+                cleaned.push_str(&fix_ocr_token(&token, before == Some('.') || called));
+                token.clear();
+            }
+            before = Some(c);
+            cleaned.push(c);
+        }
+        if !token.is_empty() {
+            cleaned.push_str(&fix_ocr_token(&token, before == Some('.')));
+        }
+    }
+    cleaned
+}
+
+// Multi-line text where most lines carry code punctuation and some structure (indentation or braces)
+fn text_looks_like_code(text: &str) -> bool {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    if lines.len() < 2 {
+        return false;
+    }
+    let code_lines = lines
+        .iter()
+        .filter(|line| {
+            looks_like_code(line)
+                || line
+                    .split(|c: char| !c.is_alphanumeric() && c != '_')
+                    .next()
+                    .is_some_and(|first| OCR_CODE_KEYWORDS.contains(&first))
+        })
+        .count();
+    let structured = text.contains('{') || lines.iter().any(|line| line.starts_with("    ") || line.starts_with('\t'));
+    code_lines * 2 >= lines.len() && structured
+}
+
+#[derive(Clone, serde::Serialize)]
+struct CodeDetected {
+    text: String,
+    preview: String,
+    lines: usize,
+}
+
+// Poll the clipboard and emit "code-detected" once per new snippet that looks like code.
+// A snippet must be unchanged across two polls before it fires, so rapid copies only emit the last one.
+#[tauri::command]
+fn watch_clipboard(enabled: bool, app: tauri::AppHandle, tasks: tauri::State<'_, BackgroundTasks>) {
+    if !enabled {
+        tasks.stop("clipboard-watch");
+        return;
+    }
+
+    let handle = tauri::async_runtime::spawn(async move {
+        // Whatever is on the clipboard when watching starts isn't news
+        let mut emitted = app.clipboard().read_text().ok();
+        let mut previous = emitted.clone();
+        loop {
+            tokio::time::sleep(Duration::from_millis(750)).await;
+            let current = app.clipboard().read_text().ok();
+            let stable = current.is_some() && current == previous;
+            if stable && current != emitted {
+                if let Some(text) = current.as_deref().filter(|text| text_looks_like_code(text)) {
+                    let preview = text.lines().take(3).collect::<Vec<_>>().join("\n").chars().take(200).collect();
+                    let _ = app.emit("code-detected", CodeDetected { text: text.to_string(), preview, lines: text.lines().count() });
+                }
+                emitted = current.clone();
+            }
+            previous = current;
+        }
+    });
+    tasks.track("clipboard-watch", handle);
+}
+
+#[tauri::command]
+fn set_ocr_cleanup(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> bool {
+    cfg.ocr_cleanup.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+#[tauri::command]
+fn clean_ocr_text(text: String) -> String {
+    cleanup_ocr_text(&text)
+}
+
+#[tauri::command]
+async fn call_gemini_with_text(prompt: String, text: String, cfg: tauri::State<'_, AppConfig>, conv: tauri::State<'_, Conversation>) -> Result<String, String> {
+    let _slot = request_slot(&cfg)?;
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+
+    // Text extracted locally (OCR) is sent instead of images to save tokens
+    let text = if cfg.ocr_cleanup.load(Ordering::SeqCst) {
+        cleanup_ocr_text(&text)
+    } else {
+        text
+    };
+
+    let user_message = format!("{}\n\n{}", prompt, text);
+    let client = gemini_client(&cfg)?;
+
+    let system = "Be concise and helpful. The content below was extracted from screenshots via OCR and may contain recognition errors.";
+    let model = model_for_request(&cfg, &user_message, &[])?;
+
+    let chat_req = ChatRequest::new(vec![
+        ChatMessage::system(system_prompt(&cfg, &model, system)),
+        ChatMessage::user(user_message.clone()),
+    ]);
+
+    let res = exec_chat(&cfg, &client, &model, chat_req.clone(), None)
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+    remember_response_meta(&conv, &res);
+
+    let answer = res
+        .content_text_as_str()
+        .unwrap_or("[No response]")
+        .to_string();
+    let answer = continue_if_truncated(&client, &model, chat_req, answer, &cfg, &conv).await;
+    record_exchange(&cfg, &conv, &user_message, &answer);
+    remember_request(&conv, RecordedRequest {
+        mode: "text".to_string(),
+        system: system.to_string(),
+        prompt: user_message,
+        image_paths: Vec::new(),
+        model,
+        answer: answer.clone(),
+    });
+    Ok(answer)
+}
+
+// None means the key is not configured or the provider could not be reached
+#[derive(Clone, PartialEq, serde::Serialize)]
+struct CredentialsStatus {
+    gemini: Option<bool>,
+    huggingface: Option<bool>,
+}
+
+// Auth rejections mark a key invalid; anything else (outage, network) is inconclusive
+fn key_validity(response: Result<reqwest::Response, reqwest::Error>) -> Option<bool> {
+    let status = response.ok()?.status();
+    if status.is_success() {
+        Some(true)
+    } else if matches!(status.as_u16(), 400 | 401 | 403) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// Cheap authenticated listing calls that don't spend generation quota
+async fn probe_credentials(cfg: &AppConfig) -> Result<CredentialsStatus, String> {
+    let api_key = cfg.api_key.lock().map_err(|_| "Lock poisoned")?.clone();
+    let hf_token = cfg.hf_token.lock().map_err(|_| "Lock poisoned")?.clone();
+    let endpoint = trusted_gemini_endpoint(cfg)?
+        .unwrap_or_else(|| "https://generativelanguage.googleapis.com/v1beta/".to_string());
+    let http_client = http_client_builder(cfg, &PinFailure::default())?
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let gemini = match api_key {
+        Some(key) => key_validity(
+            http_client
+                .get(format!("{}/models?pageSize=1", endpoint.trim_end_matches('/')))
+                .header("x-goog-api-key", key)
+                .send()
+                .await,
+        ),
+        None => None,
+    };
+    let huggingface = match hf_token {
+        Some(token) => key_validity(
+            http_client
+                .get("https://huggingface.co/api/whoami-v2")
+                .bearer_auth(token)
+                .send()
+                .await,
+        ),
+        None => None,
+    };
+    Ok(CredentialsStatus { gemini, huggingface })
+}
+
+#[tauri::command]
+async fn check_credentials(cfg: tauri::State<'_, AppConfig>) -> Result<CredentialsStatus, String> {
+    probe_credentials(&cfg).await
+}
+
+// Re-check keys on a schedule and emit "credentials-status" when the result changes to include an invalid key
+#[tauri::command]
+fn start_credentials_monitor(interval_secs: u64, app: tauri::AppHandle, tasks: tauri::State<'_, BackgroundTasks>) {
+    // Floor at 30s so a typo can't hammer the providers
+    let interval = Duration::from_secs(interval_secs.max(30));
+    let handle = tauri::async_runtime::spawn(async move {
+        let mut last: Option<CredentialsStatus> = None;
+        loop {
+            let cfg = app.state::<AppConfig>();
+            if let Ok(status) = probe_credentials(&cfg).await {
+                let invalid = status.gemini == Some(false) || status.huggingface == Some(false);
+                if invalid && last.as_ref() != Some(&status) {
+                    let _ = app.emit("credentials-status", &status);
+                }
+                last = Some(status);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+    tasks.track("credentials-monitor", handle);
+}
+
+#[tauri::command]
+fn stop_credentials_monitor(tasks: tauri::State<'_, BackgroundTasks>) -> bool {
+    tasks.stop("credentials-monitor")
+}
+
+#[tauri::command]
+fn stop_background_task(name: String, tasks: tauri::State<'_, BackgroundTasks>) -> bool {
+    tasks.stop(&name)
+}
+
+#[tauri::command]
+fn stop_all_background_tasks(cfg: tauri::State<'_, AppConfig>, tasks: tauri::State<'_, BackgroundTasks>) -> usize {
+    // The image server is among the tasks; stop advertising its URLs
+    if let Ok(mut port) = cfg.image_server_port.lock() {
+        *port = None;
+    }
+    let mut registry = tasks.tasks.lock().unwrap();
+    let mut stopped = 0;
+    for (_, handle) in registry.drain() {
+        if !handle.inner().is_finished() {
+            stopped += 1;
+        }
+        handle.abort();
+    }
+    stopped
+}
+
+#[tauri::command]
+fn quit_app(app: tauri::AppHandle) {
+    app.exit(0);
+}
+
+#[tauri::command]
+async fn add_image_to_queue(app: tauri::AppHandle, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<usize, String> {
+    // Debounce: only allow one capture per 500ms
+    {
+        let mut last_capture = queue.last_capture.lock().unwrap();
+        let now = Instant::now();
+        if now.duration_since(*last_capture) < Duration::from_millis(500) {
+            // Return current queue length without adding new image
+            let images = queue.images.lock().unwrap();
+            return Ok(images.len());
+        }
+        *last_capture = now;
+    }
+
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+
+    let title = captured_window_title(&app);
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+
+    let path = save_capture(&cfg, width, height, image.into_raw())?;
+    Ok(enqueue_capture(&queue, path, title))
+}
+
+// Append a fresh capture, or in single mode replace whatever is queued; returns the queue length.
+// `window_title` is the window the capture came from, kept as metadata and model context.
+fn enqueue_capture(queue: &ImageQueue, path: String, window_title: Option<String>) -> usize {
+    *queue.last_activity.lock().unwrap() = Instant::now();
+    if let Some(title) = window_title {
+        queue.window_titles.lock().unwrap().insert(path.clone(), title);
+    }
+    if queue.single_slot.load(Ordering::SeqCst) {
+        keep_only_latest(queue, Some(path));
+        return queue.images.lock().unwrap().len();
+    }
+    let mut images = queue.images.lock().unwrap();
+    images.push_back(path);
+    images.len()
+}
+
+// Single-slot upkeep: drop every queued image (captions, group membership) except `latest`. Only the
+// queue entries go; the files are left to prune_temp_files, which keeps pinned and still-referenced ones.
+fn keep_only_latest(queue: &ImageQueue, latest: Option<String>) {
+    let mut images = queue.images.lock().unwrap();
+    let latest = latest.or_else(|| images.back().cloned());
+    images.clear();
+    images.extend(latest.clone());
+    queue.captions.lock().unwrap().retain(|path, _| Some(path) == latest.as_ref());
+    queue.window_titles.lock().unwrap().retain(|path, _| Some(path) == latest.as_ref());
+    for members in queue.groups.lock().unwrap().values_mut() {
+        members.retain(|path| Some(path) == latest.as_ref());
+    }
+}
+
+// "append" grows the queue (default); "single" keeps exactly the newest capture
+#[tauri::command]
+fn set_queue_mode(mode: String, queue: tauri::State<'_, ImageQueue>) -> Result<usize, String> {
+    match mode.as_str() {
+        "append" => queue.single_slot.store(false, Ordering::SeqCst),
+        "single" => {
+            queue.single_slot.store(true, Ordering::SeqCst);
+            keep_only_latest(&queue, None);
+        }
+        _ => return Err(format!("Unknown queue mode '{}'; use append or single", mode)),
+    }
+    Ok(queue.images.lock().unwrap().len())
+}
+
+// Pixel coordinates on the image being annotated
+#[derive(serde::Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+enum Annotation {
+    Arrow { from_x: f32, from_y: f32, to_x: f32, to_y: f32 }, // Head at (to_x, to_y)
+    Box { x: i32, y: i32, width: u32, height: u32 },
+    Highlight { x: i32, y: i32, width: u32, height: u32 }, // Translucent fill, keeps the text readable
+}
+
+const ANNOTATION_COLOR: Rgba<u8> = Rgba([230, 30, 40, 255]);
+const HIGHLIGHT_COLOR: [u8; 3] = [255, 220, 0];
+
+fn draw_annotation(canvas: &mut image::RgbaImage, annotation: &Annotation, stroke: i32) {
+    use imageproc::drawing::{draw_hollow_rect_mut, draw_line_segment_mut};
+    use imageproc::rect::Rect;
+
+    match *annotation {
+        Annotation::Arrow { from_x, from_y, to_x, to_y } => {
+            let (dx, dy) = (to_x - from_x, to_y - from_y);
+            let length = dx.hypot(dy);
+            if length < 1.0 {
+                return;
+            }
+            let (ux, uy) = (dx / length, dy / length);
+            let head = (stroke as f32 * 6.0).min(length * 0.5);
+            // Shaft and both head strokes, thickened by parallel offsets along the normal
+            let mut segments = vec![((from_x, from_y), (to_x, to_y))];
+            for side in [-1.0f32, 1.0] {
+                let (cos, sin) = (0.9f32, 0.44 * side); // About 26 degrees off the shaft
+                let (bx, by) = (-(ux * cos - uy * sin), -(ux * sin + uy * cos));
+                segments.push(((to_x, to_y), (to_x + bx * head, to_y + by * head)));
+            }
+            for ((x1, y1), (x2, y2)) in segments {
+                let (sx, sy) = (x2 - x1, y2 - y1);
+                let norm = sx.hypot(sy).max(1.0);
+                let (nx, ny) = (-sy / norm, sx / norm);
+                for offset in -stroke / 2..=stroke / 2 {
+                    let o = offset as f32;
+                    draw_line_segment_mut(canvas, (x1 + nx * o, y1 + ny * o), (x2 + nx * o, y2 + ny * o), ANNOTATION_COLOR);
+                }
+            }
+        }
+        Annotation::Box { x, y, width, height } => {
+            for inset in 0..stroke {
+                let (w, h) = (width.saturating_sub(2 * inset as u32), height.saturating_sub(2 * inset as u32));
+                if w == 0 || h == 0 {
+                    break;
+                }
+                draw_hollow_rect_mut(canvas, Rect::at(x + inset, y + inset).of_size(w, h), ANNOTATION_COLOR);
+            }
+        }
+        Annotation::Highlight { x, y, width, height } => {
+            let (x0, y0) = (x.max(0) as u32, y.max(0) as u32);
+            let x1 = (x.saturating_add(width as i32).max(0) as u32).min(canvas.width());
+            let y1 = (y.saturating_add(height as i32).max(0) as u32).min(canvas.height());
+            for py in y0..y1 {
+                for px in x0..x1 {
+                    let pixel = canvas.get_pixel_mut(px, py);
+                    for channel in 0..3 {
+                        pixel[channel] = ((pixel[channel] as u16 * 3 + HIGHLIGHT_COLOR[channel] as u16 * 2) / 5) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Draw arrows, boxes and highlights onto a copy of an image so the model's attention can be
+// pointed at part of it; returns the copy's path, ready to send or queue
+#[tauri::command]
+async fn annotate_image(path: String, annotations: Vec<Annotation>, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    if annotations.is_empty() {
+        return Err("No annotations given".to_string());
+    }
+    let mut output = capture_dir(&cfg);
+    output.push(format!("{}_annotated.png", Uuid::new_v4()));
+    run_image_job(&cfg, move || {
+        let mut canvas = image::open(&path).map_err(|e| format!("{}: {}", image_name(&path), e))?.to_rgba8();
+        // Strokes grow with the image so they stay visible after the model's downscaling
+        let stroke = (canvas.width().min(canvas.height()) / 300).max(2) as i32;
+        // Highlights first, so outlines and arrows stay on top
+        let (highlights, marks): (Vec<_>, Vec<_>) = annotations.iter().partition(|a| matches!(a, Annotation::Highlight { .. }));
+        for annotation in highlights.into_iter().chain(marks) {
+            draw_annotation(&mut canvas, annotation, stroke);
+        }
+        canvas.save(&output).map_err(|e| e.to_string())?;
+        Ok(output.to_string_lossy().to_string())
+    })
+    .await
+}
+
+#[derive(serde::Serialize)]
+struct StitchedImage {
+    path: String,
+    width: u32,
+    height: u32,
+    downscaled: bool,
+}
+
+// Longest side the model accepts before it rejects or silently rescales an image
+fn max_image_dimension(model: &str) -> u32 {
+    if model.starts_with("gemini") {
+        3072
+    } else {
+        2048
+    }
+}
+
+// Name fragments of models known to take text only; checked after the vision markers below
+const TEXT_ONLY_MODELS: [&str; 10] = [
+    "gpt-oss", "gpt-3.5", "o1-mini", "o3-mini", "deepseek", "llama", "mistral", "mixtral", "qwen", "command-r",
+];
+
+fn model_supports_vision(model: &str) -> bool {
+    let model = model.to_lowercase();
+    if ["vision", "llava", "-vl", "pixtral", "llama-4", "llama4"].iter().any(|marker| model.contains(marker)) {
+        return true;
+    }
+    !TEXT_ONLY_MODELS.iter().any(|name| model.contains(name))
+}
+
+// An image as sent to the model: inline for vision models, otherwise its OCR text.
+// With an OCR confidence threshold set, vision models get the text instead when OCR is confident enough.
+async fn image_content(cfg: &AppConfig, image_path: &str, vision: bool) -> Result<ContentPart, String> {
+    let threshold = *cfg.ocr_min_confidence.lock().map_err(|_| "Lock poisoned")?;
+    let preserve_indent = cfg.ocr_preserve_indent.load(Ordering::SeqCst);
+    let (part, route) = match (vision, threshold) {
+        (true, None) => (encode_image(cfg, image_path).await?, ImageRoute::image(image_path, None)),
+        (true, Some(threshold)) => match ocr_image_scored(cfg, image_path, preserve_indent).await {
+            Ok(ocr) if ocr.confidence >= threshold && !ocr.text.trim().is_empty() => {
+                (ocr_text_part(image_path, &ocr.text), ImageRoute::text(image_path, Some(ocr.confidence)))
+            }
+            // Unsure or failed OCR: the image itself is the safe choice
+            Ok(ocr) => (encode_image(cfg, image_path).await?, ImageRoute::image(image_path, Some(ocr.confidence))),
+            Err(_) => (encode_image(cfg, image_path).await?, ImageRoute::image(image_path, None)),
+        },
+        (false, _) => {
+            let ocr = ocr_image_scored(cfg, image_path, preserve_indent).await.map_err(|e| {
+                format!("The selected model doesn't support images, and reading {} with OCR failed: {}", image_name(image_path), e)
+            })?;
+            (ocr_text_part(image_path, &ocr.text), ImageRoute::text(image_path, Some(ocr.confidence)))
+        }
+    };
+    cfg.image_routes.lock().map_err(|_| "Lock poisoned")?.insert(image_path.to_string(), route);
+    Ok(part)
+}
+
+fn ocr_text_part(image_path: &str, text: &str) -> ContentPart {
+    ContentPart::from_text(format!("[Text recognized in {}]\n{}", image_name(image_path), text))
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ImageRoute {
+    path: String,
+    sent_as: &'static str, // "image" or "text"
+    confidence: Option<f32>, // OCR confidence, when OCR ran
+}
+
+impl ImageRoute {
+    fn image(path: &str, confidence: Option<f32>) -> Self {
+        ImageRoute { path: path.to_string(), sent_as: "image", confidence }
+    }
+
+    fn text(path: &str, confidence: Option<f32>) -> Self {
+        ImageRoute { path: path.to_string(), sent_as: "text", confidence }
+    }
+}
+
+// Send confidently OCR'd images as text (cheaper) and the rest as images; None always sends images
+#[tauri::command]
+fn set_ocr_min_confidence(threshold: Option<f32>, cfg: tauri::State<'_, AppConfig>) -> Result<Option<f32>, String> {
+    let threshold = threshold.map(|t| t.clamp(0.0, 100.0));
+    *cfg.ocr_min_confidence.lock().map_err(|_| "Lock poisoned")? = threshold;
+    Ok(threshold)
+}
+
+// How each queued image was last sent, in queue order; images not sent yet are omitted
+#[tauri::command]
+fn get_image_routes(queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<Vec<ImageRoute>, String> {
+    let routes = cfg.image_routes.lock().map_err(|_| "Lock poisoned")?;
+    Ok(queue.images.lock().unwrap().iter().filter_map(|path| routes.get(path).cloned()).collect())
+}
+
+#[derive(serde::Serialize)]
+struct ModelCapabilities {
+    model: String,
+    provider: String,
+    vision: bool,
+    image_fallback: Option<String>, // How images reach a text-only model
+    max_image_dimension: u32,
+}
+
+// Capabilities of `model`, or of the active model when omitted
+#[tauri::command]
+fn get_model_capabilities(model: Option<String>, cfg: tauri::State<'_, AppConfig>) -> Result<ModelCapabilities, String> {
+    let model = match model {
+        Some(model) => model,
+        None => cfg.model.lock().map_err(|_| "Lock poisoned")?.clone(),
+    };
+    let vision = model_supports_vision(&model);
+    Ok(ModelCapabilities {
+        provider: model_provider(&model),
+        vision,
+        image_fallback: (!vision).then(|| "ocr".to_string()),
+        max_image_dimension: max_image_dimension(&model),
+        model,
+    })
+}
+
+// Stack images vertically on a white canvas, then shrink the result to fit `max_side`
+fn stitch_images(paths: &[String], max_side: u32, dir: PathBuf) -> Result<StitchedImage, String> {
+    let images = paths
+        .iter()
+        .map(|path| image::open(path).map(|img| img.to_rgba8()).map_err(|e| format!("{}: {}", image_name(path), e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let width = images.iter().map(|img| img.width()).max().unwrap_or(0);
+    let height: u32 = images.iter().map(|img| img.height()).sum();
+    if width == 0 || height == 0 {
+        return Err("Nothing to stitch".to_string());
+    }
+
+    let mut canvas = ImageBuffer::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+    let mut y = 0;
+    for img in &images {
+        image::imageops::overlay(&mut canvas, img, 0, y as i64);
+        y += img.height();
+    }
+
+    let downscaled = width.max(height) > max_side;
+    let canvas = if downscaled {
+        let scale = max_side as f64 / width.max(height) as f64;
+        let (w, h) = (((width as f64 * scale) as u32).max(1), ((height as f64 * scale) as u32).max(1));
+        image::imageops::resize(&canvas, w, h, image::imageops::FilterType::Lanczos3)
+    } else {
+        canvas
+    };
+
+    let mut path = dir;
+    path.push(format!("{}_stitched.png", Uuid::new_v4()));
+    canvas.save(&path).map_err(|e| e.to_string())?;
+    Ok(StitchedImage {
+        path: path.to_string_lossy().to_string(),
+        width: canvas.width(),
+        height: canvas.height(),
+        downscaled,
+    })
+}
+
+// Per-row signature: mean luminance of 32 column bins, so rows compare cheaply and tolerate minor noise
+fn row_signatures(img: &image::RgbaImage) -> Vec<[u8; 32]> {
+    let luma = image::DynamicImage::ImageRgba8(img.clone()).to_luma8();
+    let (width, height) = luma.dimensions();
+    (0..height)
+        .map(|y| {
+            let mut signature = [0u8; 32];
+            for (bin, value) in signature.iter_mut().enumerate() {
+                let x0 = bin as u32 * width / 32;
+                let x1 = ((bin as u32 + 1) * width / 32).max(x0 + 1).min(width);
+                let sum: u32 = (x0..x1).map(|x| luma.get_pixel(x, y)[0] as u32).sum();
+                *value = (sum / x1.saturating_sub(x0).max(1)) as u8;
+            }
+            signature
+        })
+        .collect()
+}
+
+// Rows at the bottom of `top` repeated at the top of `bottom` (after scrolling), or 0 when none are found.
+// Blank rows match anything, so an overlap only counts when it includes some rows with content.
+fn vertical_overlap(top: &image::RgbaImage, bottom: &image::RgbaImage) -> u32 {
+    const MIN_OVERLAP: usize = 16;
+    if top.width() != bottom.width() {
+        return 0;
+    }
+    let (above, below) = (row_signatures(top), row_signatures(bottom));
+    let similar = |a: &[u8; 32], b: &[u8; 32]| a.iter().zip(b).map(|(x, y)| x.abs_diff(*y) as u32).sum::<u32>() <= 32 * 4;
+    let has_content = |row: &[u8; 32]| row.iter().max().unwrap_or(&0) - row.iter().min().unwrap_or(&0) > 24;
+
+    let max_overlap = above.len().min(below.len());
+    (MIN_OVERLAP..=max_overlap)
+        .rev()
+        .find(|&rows| {
+            let tail = &above[above.len() - rows..];
+            let head = &below[..rows];
+            tail.iter().filter(|row| has_content(row)).count() * 4 >= rows && tail.iter().zip(head).all(|(a, b)| similar(a, b))
+        })
+        .unwrap_or(0) as u32
+}
+
+// Bounding box (x, y, w, h) of the 8px cells whose mean luminance moved noticeably between two same-sized frames
+fn changed_bounds(before: &image::RgbaImage, after: &image::RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    const CELL: u32 = 8;
+    const MIN_MEAN_DELTA: u32 = 12;
+    let (width, height) = after.dimensions();
+    if before.dimensions() != (width, height) {
+        return Some((0, 0, width, height));
+    }
+    let (a, b) = (
+        image::DynamicImage::ImageRgba8(before.clone()).to_luma8(),
+        image::DynamicImage::ImageRgba8(after.clone()).to_luma8(),
+    );
+    let mut bounds: Option<(u32, u32, u32, u32)> = None; // (x0, y0, x1, y1)
+    for cy in (0..height).step_by(CELL as usize) {
+        for cx in (0..width).step_by(CELL as usize) {
+            let (x1, y1) = ((cx + CELL).min(width), (cy + CELL).min(height));
+            let delta: u32 = (cy..y1)
+                .flat_map(|y| (cx..x1).map(move |x| (x, y)))
+                .map(|(x, y)| a.get_pixel(x, y)[0].abs_diff(b.get_pixel(x, y)[0]) as u32)
+                .sum();
+            if delta / ((x1 - cx) * (y1 - cy)) >= MIN_MEAN_DELTA {
+                bounds = Some(match bounds {
+                    Some((bx0, by0, bx1, by1)) => (bx0.min(cx), by0.min(cy), bx1.max(x1), by1.max(y1)),
+                    None => (cx, cy, x1, y1),
+                });
+            }
+        }
+    }
+    bounds.map(|(x0, y0, x1, y1)| (x0, y0, x1 - x0, y1 - y0))
+}
+
+#[derive(serde::Serialize)]
+struct NewContentCapture {
+    kind: &'static str, // "initial", "scrolled", "changed" or "unchanged"
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    path: Option<String>, // None when nothing new was queued
+    queue_length: usize,
+    note: Option<String>,
+}
+
+// Capture the screen and queue only what is new since the previous call: the rows revealed by a scroll,
+// or else the bounding box of what changed. The first call queues the whole screen.
+#[tauri::command]
+async fn capture_new_content(app: tauri::AppHandle, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<NewContentCapture, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+
+    let title = captured_window_title(&app);
+    let image = grab_screen(&app, &cfg, screen).await?;
+    let (width, height) = (image.width(), image.height());
+    let frame = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, image.into_raw())
+        .ok_or("Failed to convert image")?;
+    let previous = queue.last_screen.lock().unwrap().replace(frame.clone());
+
+    let (kind, region) = match previous.filter(|previous| previous.dimensions() == (width, height)) {
+        None => ("initial", Some((0, 0, width, height))),
+        // A full-height overlap is no scroll; the cell comparison decides whether anything changed
+        Some(previous) => match vertical_overlap(&previous, &frame) {
+            rows if rows > 0 && rows < height => ("scrolled", Some((0, rows, width, height - rows))),
+            _ => match changed_bounds(&previous, &frame) {
+                Some(bounds) => ("changed", Some(bounds)),
+                None => ("unchanged", None),
+            },
+        },
+    };
+
+    let Some((x, y, w, h)) = region else {
+        return Ok(NewContentCapture {
+            kind,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            path: None,
+            queue_length: queue.images.lock().unwrap().len(),
+            note: Some("Nothing changed since the previous capture".to_string()),
+        });
+    };
+    let crop = image::imageops::crop_imm(&frame, x, y, w, h).to_image();
+    let path = save_capture(&cfg, w, h, crop.into_raw())?;
+    let queue_length = enqueue_capture(&queue, path.clone(), title);
+    Ok(NewContentCapture { kind, x, y, width: w, height: h, path: Some(path), queue_length, note: None })
+}
+
+#[derive(serde::Serialize)]
+struct SessionImage {
+    path: String,
+    width: u32,
+    height: u32,
+    overlap_rows: u32, // Rows of the new capture dropped as already present
+}
+
+// Stitch a capture (the given image, or a fresh full-screen capture) onto the bottom of the session image.
+// The session image is queued once and grows in place; reset_session_image starts a new one.
+#[tauri::command]
+async fn append_to_session_image(
+    path: Option<String>,
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<SessionImage, String> {
+    // Only captures taken here are deleted once merged; a given file is left alone
+    let owned = path.is_none();
+    let title = if owned { captured_window_title(&app) } else { None };
+    let capture = match path {
+        Some(path) => path,
+        None => capture_full_screen(app, cfg.clone()).await?,
+    };
+    let session = queue.session_image.lock().unwrap().clone();
+    // A session image dropped from the queue (sent, cleared) means a fresh session
+    let session = session.filter(|session| queue.images.lock().unwrap().contains(session));
+
+    let Some(session) = session else {
+        let img = image::open(&capture).map_err(|e| e.to_string())?;
+        *queue.session_image.lock().unwrap() = Some(capture.clone());
+        enqueue_capture(&queue, capture.clone(), title);
+        return Ok(SessionImage { path: capture, width: img.width(), height: img.height(), overlap_rows: 0 });
+    };
+
+    let target = session.clone();
+    let result = run_image_job(&cfg, move || {
+        let top = image::open(&target).map_err(|e| e.to_string())?.to_rgba8();
+        let bottom = image::open(&capture).map_err(|e| e.to_string())?.to_rgba8();
+        let overlap_rows = vertical_overlap(&top, &bottom);
+        let fresh = bottom.height() - overlap_rows;
+        let width = top.width().max(bottom.width());
+        let mut canvas = ImageBuffer::from_pixel(width, top.height() + fresh, Rgba([255, 255, 255, 255]));
+        image::imageops::overlay(&mut canvas, &top, 0, 0);
+        let new_rows = image::imageops::crop_imm(&bottom, 0, overlap_rows, bottom.width(), fresh).to_image();
+        image::imageops::overlay(&mut canvas, &new_rows, 0, top.height() as i64);
+        canvas.save(&target).map_err(|e| e.to_string())?;
+        if owned {
+            let _ = fs::remove_file(&capture);
+        }
+        Ok(SessionImage { path: target, width: canvas.width(), height: canvas.height(), overlap_rows })
+    })
+    .await?;
+    *queue.last_activity.lock().unwrap() = Instant::now();
+    Ok(result)
+}
+
+// Forget the session image; it stays queued as a normal image
+#[tauri::command]
+fn reset_session_image(queue: tauri::State<'_, ImageQueue>) -> Option<String> {
+    queue.session_image.lock().unwrap().take()
+}
+
+// Combine the queue into one tall image that stays within the current model's size limit
+#[tauri::command]
+async fn stitch_queue(queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<StitchedImage, String> {
+    let image_paths = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    if image_paths.is_empty() {
+        return Err("No images in queue".to_string());
+    }
+    let max_side = max_image_dimension(&cfg.model.lock().map_err(|_| "Lock poisoned")?);
+    let dir = capture_dir(&cfg);
+    run_image_job(&cfg, move || stitch_images(&image_paths, max_side, dir)).await
+}
+
+#[derive(serde::Serialize)]
+struct SplitCapture {
+    first: String,
+    second: String,
+}
+
+// Split a capture into two panes ("left-right" or "top-bottom") and queue both with captions
+#[tauri::command]
+fn split_capture(path: String, orientation: String, queue: tauri::State<'_, ImageQueue>) -> Result<SplitCapture, String> {
+    if queue.single_slot.load(Ordering::SeqCst) {
+        return Err("Splitting queues two panes; switch the queue to append mode first".to_string());
+    }
+    let img = image::open(&path).map_err(|e| e.to_string())?;
+    let (width, height) = (img.width(), img.height());
+    let (panes, labels) = match orientation.as_str() {
+        "left-right" if width >= 2 => (
+            [(0, 0, width / 2, height), (width / 2, 0, width - width / 2, height)],
+            ["left", "right"],
+        ),
+        "top-bottom" if height >= 2 => (
+            [(0, 0, width, height / 2), (0, height / 2, width, height - height / 2)],
+            ["top", "bottom"],
+        ),
+        "left-right" | "top-bottom" => return Err("Image is too small to split".to_string()),
+        _ => return Err(format!("Unknown orientation '{}'; use left-right or top-bottom", orientation)),
+    };
+
+    let paths = labels.map(|label| format!("{}_{}.png", path.trim_end_matches(".png"), label));
+    for ((x, y, w, h), pane_path) in panes.into_iter().zip(&paths) {
+        img.crop_imm(x, y, w, h).save(pane_path).map_err(|e| e.to_string())?;
+    }
+
+    *queue.last_activity.lock().unwrap() = Instant::now();
+    let mut images = queue.images.lock().unwrap();
+    let mut captions = queue.captions.lock().unwrap();
+    let mut titles = queue.window_titles.lock().unwrap();
+    let title = titles.get(&path).cloned();
+    for (pane_path, label) in paths.iter().zip(labels) {
+        images.push_back(pane_path.clone());
+        captions.insert(pane_path.clone(), format!("The following image is the {} pane of the same screen:", label));
+        if let Some(title) = &title {
+            titles.insert(pane_path.clone(), title.clone());
+        }
+    }
+    let [first, second] = paths;
+    Ok(SplitCapture { first, second })
+}
+
+#[derive(Clone, serde::Serialize)]
+struct CaptureQueued {
+    queue_length: Option<usize>,
+    error: Option<String>,
+}
+
+// Bind (or rebind) the capture-and-queue hotkey natively so it fires even when the webview lacks focus.
+// Each press emits "capture-queued" with the new queue length or the capture error.
+// The chord is parsed before anything is unbound, and a chord the OS rejects leaves the old one in place.
+// Returns the normalized chord (e.g. "shift+control+alt+KeyS"), or None once cleared with an empty string.
+#[tauri::command]
+fn register_capture_shortcut(accelerator: String, state: tauri::State<ToggleState>, app: tauri::AppHandle) -> Result<Option<String>, String> {
+    let mut current = state.capture_shortcut.lock().map_err(|_| "Lock poisoned")?;
+    let shortcuts = app.global_shortcut();
+    if accelerator.trim().is_empty() {
+        if let Some(previous) = current.take() {
+            shortcuts.unregister(previous.as_str()).map_err(|e| e.to_string())?;
+        }
+        return Ok(None);
+    }
+
+    let shortcut = accelerator
+        .trim()
+        .parse::<Shortcut>()
+        .map_err(|e| format!("Invalid shortcut '{}': {}", accelerator, e))?;
+    let normalized = shortcut.into_string();
+    if current.as_deref() == Some(normalized.as_str()) && shortcuts.is_registered(shortcut) {
+        return Ok(Some(normalized));
+    }
+
+    if let Some(previous) = current.as_deref() {
+        shortcuts.unregister(previous).map_err(|e| e.to_string())?;
+    }
+    if let Err(e) = shortcuts.on_shortcut(shortcut, on_capture_shortcut) {
+        if let Some(previous) = current.as_deref() {
+            if shortcuts.on_shortcut(previous, on_capture_shortcut).is_err() {
+                *current = None;
+            }
+        }
+        return Err(format!("Could not register '{}': {}", normalized, e));
+    }
+    *current = Some(normalized.clone());
+    Ok(Some(normalized))
+}
+
+fn on_capture_shortcut(app: &tauri::AppHandle, _shortcut: &Shortcut, event: ShortcutEvent) {
+    if event.state != ShortcutState::Pressed {
+        return;
+    }
+    // Captured off the event loop, which the shortcut handler runs on
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let result = add_image_to_queue(app.clone(), app.state::<ImageQueue>(), app.state::<AppConfig>()).await;
+        let payload = match result {
+            Ok(length) => CaptureQueued { queue_length: Some(length), error: None },
+            Err(e) => CaptureQueued { queue_length: None, error: Some(e) },
+        };
+        let _ = app.emit("capture-queued", payload);
+    });
+}
+
+#[derive(serde::Serialize)]
+struct QueueFootprint {
+    images: usize,
+    bytes_on_disk: u64,
+    estimated_payload_bytes: u64, // Base64 size of the images as they would be sent
+    missing: usize, // Queued paths whose file no longer exists
+}
+
+#[tauri::command]
+fn get_queue_footprint(queue: tauri::State<'_, ImageQueue>) -> QueueFootprint {
+    let images = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    let sizes: Vec<Option<u64>> = images.iter().map(|path| fs::metadata(path).ok().map(|m| m.len())).collect();
+    QueueFootprint {
+        images: images.len(),
+        bytes_on_disk: sizes.iter().flatten().sum(),
+        estimated_payload_bytes: sizes.iter().flatten().map(|len| len.div_ceil(3) * 4).sum(),
+        missing: sizes.iter().filter(|size| size.is_none()).count(),
+    }
+}
+
+// Rough token count: about four characters per token for prose, more tokens per word for dense code
+fn approximate_tokens(text: &str) -> u32 {
+    let chars = text.chars().count() as u32;
+    let words = text.split_whitespace().count() as u32;
+    chars.div_ceil(4).max((words * 4).div_ceil(3))
+}
+
+// Input tokens an image of this size costs on `model`, following each provider's published rules
+fn image_token_cost(model: &str, width: u32, height: u32) -> u32 {
+    let model = model.to_lowercase();
+    if model.starts_with("gemini") {
+        // Small images are one 258-token tile; larger ones are cut into 768px tiles
+        if width <= 384 && height <= 384 {
+            return 258;
+        }
+        return width.div_ceil(768) * height.div_ceil(768) * 258;
+    }
+    if model.starts_with("claude") {
+        return (width as u64 * height as u64 / 750) as u32;
+    }
+    // OpenAI-style: fit within 2048, shorten the short side to 768, then 170 per 512px tile plus 85
+    let (mut w, mut h) = (width as f64, height as f64);
+    let fit = (2048.0 / w.max(h)).min(1.0);
+    (w, h) = (w * fit, h * fit);
+    let shorten = (768.0 / w.min(h)).min(1.0);
+    (w, h) = (w * shorten, h * shorten);
+    85 + 170 * ((w / 512.0).ceil() * (h / 512.0).ceil()) as u32
+}
+
+// Context window of the known model families, for warning before an oversized send
+fn context_window(model: &str) -> Option<u32> {
+    let model = model.to_lowercase();
+    if model.starts_with("gemini-1.5-pro") {
+        Some(2_000_000)
+    } else if model.starts_with("gemini") {
+        Some(1_000_000)
+    } else if model.starts_with("claude") {
+        Some(200_000)
+    } else if model.starts_with("gpt-4o") || model.contains("gpt-oss") {
+        Some(128_000)
+    } else {
+        None
+    }
+}
+
+const ADAPTIVE_FAST_MODEL: &str = "gemini-2.0-flash";
+const ADAPTIVE_STRONG_MODEL: &str = "gemini-2.5-pro";
+// Cheap vision model that transcribes the queue for detect_problem_type
+const DETECTION_MODEL: &str = "gemini-2.0-flash";
+
+// Extracted content each captured region counts for when sizing a request, whatever its pixel size
+const ADAPTIVE_REGION_TOKENS: u32 = 500;
+
+// The model for a request: the configured one, or with adaptive selection (and no model explicitly chosen)
+// the fast model for requests whose extracted content is under the threshold and the strong model for
+// larger ones (see get_last_response_meta). Content is the prompt, the OCR'd screen context and the regions sent.
+fn model_for_request(cfg: &AppConfig, prompt: &str, image_paths: &[String]) -> Result<String, String> {
+    let threshold = *cfg.adaptive_threshold.lock().map_err(|_| "Lock poisoned")?;
+    let Some(threshold) = threshold.filter(|_| !cfg.model_explicit.load(Ordering::SeqCst)) else {
+        return Ok(cfg.model.lock().map_err(|_| "Lock poisoned")?.clone());
+    };
+    let context_tokens = cfg.screen_context.lock().map_err(|_| "Lock poisoned")?.as_ref().map_or(0, |context| approximate_tokens(&context.text));
+    let size = approximate_tokens(prompt) + context_tokens + image_paths.len() as u32 * ADAPTIVE_REGION_TOKENS;
+    Ok(if size >= threshold { ADAPTIVE_STRONG_MODEL } else { ADAPTIVE_FAST_MODEL }.to_string())
+}
+
+#[derive(serde::Serialize)]
+struct AdaptiveModel {
+    enabled: bool,
+    threshold: u32, // Estimated request tokens at which the strong model takes over
+    fast_model: &'static str,
+    strong_model: &'static str,
+}
+
+// Pick the model per request by size: small questions go to gemini-2.0-flash, ones of `threshold`
+// estimated tokens of extracted content or more (each captured region counts 500) to gemini-2.5-pro.
+// Enabling hands model choice back to adaptive selection until set_model picks one again.
+#[tauri::command]
+fn set_adaptive_model(enabled: bool, threshold: Option<u32>, cfg: tauri::State<'_, AppConfig>) -> Result<AdaptiveModel, String> {
+    let threshold = threshold.unwrap_or(2000);
+    if threshold == 0 {
+        return Err("Threshold must be at least 1 token".to_string());
+    }
+    *cfg.adaptive_threshold.lock().map_err(|_| "Lock poisoned")? = enabled.then_some(threshold);
+    if enabled {
+        cfg.model_explicit.store(false, Ordering::SeqCst);
+    }
+    Ok(AdaptiveModel { enabled, threshold, fast_model: ADAPTIVE_FAST_MODEL, strong_model: ADAPTIVE_STRONG_MODEL })
+}
+
+// Approximate token count of a piece of text
+#[tauri::command]
+fn estimate_tokens(text: String) -> u32 {
+    approximate_tokens(&text)
+}
+
+#[derive(serde::Serialize)]
+struct QueueTokenEstimate {
+    model: String,
+    text_tokens: u32, // Prompt, system prompt and captions
+    image_tokens: u32, // Images, or their OCR text for text-only models
+    per_image: Vec<u32>,
+    total: u32,
+    context_window: Option<u32>,
+    exceeds_context: bool,
+}
+
+// Estimate what sending `prompt` with the whole queue would cost on the current model (or `model`)
+#[tauri::command]
+async fn estimate_queue_tokens(
+    prompt: String,
+    model: Option<String>,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<QueueTokenEstimate, String> {
+    let model = match model {
+        Some(model) => model,
+        None => cfg.model.lock().map_err(|_| "Lock poisoned")?.clone(),
+    };
+    let images = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    let captions = queue.captions.lock().unwrap().clone();
+    let titles = queue.window_titles.lock().unwrap().clone();
+
+    let mut text_tokens = approximate_tokens(&prompt) + approximate_tokens(&system_prompt(&cfg, &model, QUEUE_SYSTEM));
+    let vision = model_supports_vision(&model);
+    let mut per_image = Vec::new();
+    for image in &images {
+        text_tokens += captions.get(image).map_or(0, |caption| approximate_tokens(caption));
+        text_tokens += titles.get(image).map_or(0, |title| approximate_tokens(title) + 12);
+        let cost = if vision {
+            let (width, height) = image::image_dimensions(image).map_err(|e| format!("{}: {}", image_name(image), e))?;
+            image_token_cost(&model, width, height)
+        } else {
+            // Text-only models get the OCR text instead
+            approximate_tokens(&ocr_image(&cfg, image).await?)
+        };
+        per_image.push(cost);
+    }
+
+    let image_tokens = per_image.iter().sum();
+    let total = text_tokens + image_tokens;
+    let context_window = context_window(&model);
+    Ok(QueueTokenEstimate {
+        exceeds_context: context_window.is_some_and(|limit| total > limit),
+        model,
+        text_tokens,
+        image_tokens,
+        per_image,
+        total,
+        context_window,
+    })
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct QueueManifestEntry {
+    file: String, // Relative to the manifest's folder
+    caption: Option<String>,
+}
+
+const QUEUE_MANIFEST: &str = "queue.json";
+
+// Copy an outside image into the capture folder as PNG, so queue clean-up never deletes the original
+fn adopt_image(cfg: &AppConfig, source: &std::path::Path) -> Result<String, String> {
+    let img = image::open(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+    let mut path = capture_dir(cfg);
+    path.push(format!("{}.png", Uuid::new_v4()));
+    img.save(&path).map_err(|e| e.to_string())?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+// Copy the queued images and their captions into `path` (a folder) with a queue.json manifest
+#[tauri::command]
+fn export_queue(path: String, queue: tauri::State<'_, ImageQueue>) -> Result<usize, String> {
+    let images = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    if images.is_empty() {
+        return Err("No images in queue".to_string());
+    }
+    write_queue_folder(&queue, &images, &PathBuf::from(&path))
+}
+
+// Copy `images` (with their captions) into `folder` next to a queue.json manifest; returns the count
+fn write_queue_folder(queue: &ImageQueue, images: &[String], folder: &std::path::Path) -> Result<usize, String> {
+    let captions = queue.captions.lock().unwrap().clone();
+    fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+
+    let mut manifest = Vec::new();
+    for (index, image) in images.iter().enumerate() {
+        let file = format!("{:03}_{}", index + 1, image_name(image));
+        fs::copy(image, folder.join(&file)).map_err(|e| format!("{}: {}", image_name(image), e))?;
+        manifest.push(QueueManifestEntry { file, caption: captions.get(image).cloned() });
+    }
+    let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    fs::write(folder.join(QUEUE_MANIFEST), json).map_err(|e| e.to_string())?;
+    Ok(manifest.len())
+}
+
+// Append the images (with captions) from a folder written by export_queue; returns the queue length
+#[tauri::command]
+fn import_queue(path: String, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<usize, String> {
+    let adopted = read_queue_folder(&cfg, &PathBuf::from(&path))?;
+    append_images(&queue, adopted)
+}
+
+// Adopt every image listed in a folder's queue.json, as (copy, caption) pairs in manifest order
+fn read_queue_folder(cfg: &AppConfig, folder: &std::path::Path) -> Result<Vec<(String, Option<String>)>, String> {
+    let json = fs::read_to_string(folder.join(QUEUE_MANIFEST)).map_err(|e| format!("{}: {}", QUEUE_MANIFEST, e))?;
+    let manifest: Vec<QueueManifestEntry> = serde_json::from_str(&json).map_err(|e| format!("{}: {}", QUEUE_MANIFEST, e))?;
+    // Manifest files must stay inside the folder
+    if let Some(entry) = manifest.iter().find(|entry| std::path::Path::new(&entry.file).components().count() != 1) {
+        return Err(format!("Invalid file name in {}: {}", QUEUE_MANIFEST, entry.file));
+    }
+    adopt_images(cfg, manifest.iter().map(|entry| (folder.join(&entry.file), entry.caption.clone())))
+}
+
+// Adopt each (source, caption) pair in order; if one fails, the copies already made are deleted
+fn adopt_images(cfg: &AppConfig, sources: impl Iterator<Item = (PathBuf, Option<String>)>) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut adopted = Vec::new();
+    for (source, caption) in sources {
+        match adopt_image(cfg, &source) {
+            Ok(copy) => adopted.push((copy, caption)),
+            Err(e) => {
+                for (copy, _) in &adopted {
+                    let _ = fs::remove_file(copy);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(adopted)
+}
+
+// Append outside images (copied, not moved) to the queue; returns the queue length
+#[tauri::command]
+fn merge_queue(other_paths: Vec<String>, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<usize, String> {
+    let adopted = adopt_images(&cfg, other_paths.iter().map(|path| (PathBuf::from(path), None)))?;
+    append_images(&queue, adopted)
+}
+
+fn append_images(queue: &ImageQueue, adopted: Vec<(String, Option<String>)>) -> Result<usize, String> {
+    if queue.single_slot.load(Ordering::SeqCst) && adopted.len() > 1 {
+        for (copy, _) in &adopted {
+            let _ = fs::remove_file(copy);
+        }
+        return Err("Importing several images needs the queue in append mode".to_string());
+    }
+    let mut length = queue.images.lock().unwrap().len();
+    for (copy, caption) in adopted {
+        if let Some(caption) = caption {
+            queue.captions.lock().unwrap().insert(copy.clone(), caption);
+        }
+        length = enqueue_capture(queue, copy, None);
+    }
+    Ok(length)
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct WindowGeometry {
+    x: i32, // Physical pixels
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+// session.json inside a named session's folder; the queue images sit beside it with their own queue.json
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedSession {
+    saved_at: u64, // Unix seconds
+    window: Option<WindowGeometry>,
+    model: String,
+    prompt: Option<String>,
+    conversation: Vec<ConversationTurn>,
+}
+
+#[derive(serde::Serialize)]
+struct SessionSummary {
+    name: String,
+    saved_at: u64,
+    model: String,
+    images: usize,
+    turns: usize,
+}
+
+#[derive(serde::Serialize)]
+struct RestoredSession {
+    model: String,
+    prompt: Option<String>,
+    queue_length: usize,
+    turns: usize,
+}
+
+const SESSION_FILE: &str = "session.json";
+
+// Folder of a named session; names are single path components so they can't escape the sessions folder
+fn session_dir(app: &tauri::AppHandle, name: &str) -> Result<PathBuf, String> {
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', ':']) {
+        return Err(format!("Invalid session name '{}'", name));
+    }
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?.join("sessions").join(name);
+    Ok(dir)
+}
+
+// Save the overlay's position and size, the queue (files included), the model, an optional prompt
+// and the conversation as a named session, replacing any earlier session of that name
+#[tauri::command]
+fn save_session(
+    name: String,
+    prompt: Option<String>,
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+    conv: tauri::State<'_, Conversation>,
+) -> Result<SessionSummary, String> {
+    let dir = session_dir(&app, &name)?;
+    // Write into a scratch folder first so a failed save leaves the previous session intact
+    let staging = dir.with_extension("saving");
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+
+    let window = app.get_webview_window("main").and_then(|window| {
+        let position = window.outer_position().ok()?;
+        let size = window.inner_size().ok()?;
+        Some(WindowGeometry { x: position.x, y: position.y, width: size.width, height: size.height })
+    });
+    let images = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    let session = SavedSession {
+        saved_at: unix_now(),
+        window,
+        model: cfg.model.lock().map_err(|_| "Lock poisoned")?.clone(),
+        prompt,
+        conversation: conv.turns.lock().unwrap().clone(),
+    };
+    let written = write_queue_folder(&queue, &images, &staging).and_then(|_| {
+        let json = serde_json::to_string_pretty(&session).map_err(|e| e.to_string())?;
+        fs::write(staging.join(SESSION_FILE), json).map_err(|e| e.to_string())
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+    let _ = fs::remove_dir_all(&dir);
+    fs::rename(&staging, &dir).map_err(|e| e.to_string())?;
+
+    Ok(SessionSummary {
+        name: name.trim().to_string(),
+        saved_at: session.saved_at,
+        model: session.model,
+        images: images.len(),
+        turns: session.conversation.len(),
+    })
+}
+
+// Bring a named session back: window geometry, model and conversation are replaced and the
+// queue is swapped for the session's images (pinned images stay)
+#[tauri::command]
+fn restore_session(
+    name: String,
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+    conv: tauri::State<'_, Conversation>,
+) -> Result<RestoredSession, String> {
+    let dir = session_dir(&app, &name)?;
+    let json = fs::read_to_string(dir.join(SESSION_FILE)).map_err(|_| format!("No session named '{}'", name.trim()))?;
+    let session: SavedSession = serde_json::from_str(&json).map_err(|e| format!("Session '{}' is corrupt: {}", name.trim(), e))?;
+    // Adopt the images before touching anything, so a damaged session doesn't leave the queue half-replaced
+    let adopted = read_queue_folder(&cfg, &dir)?;
+
+    clear_queue(queue.clone());
+    let queue_length = if adopted.is_empty() { queue.images.lock().unwrap().len() } else { append_images(&queue, adopted)? };
+    *cfg.model.lock().map_err(|_| "Lock poisoned")? = session.model.clone();
+    *conv.turns.lock().unwrap() = session.conversation.clone();
+    if let (Some(geometry), Some(window)) = (&session.window, app.get_webview_window("main")) {
+        let _ = window.set_size(Size::Physical(tauri::PhysicalSize { width: geometry.width, height: geometry.height }));
+        let _ = window.set_position(Position::Physical(PhysicalPosition { x: geometry.x, y: geometry.y }));
+    }
+
+    Ok(RestoredSession { model: session.model, prompt: session.prompt, queue_length, turns: session.conversation.len() })
+}
+
+// Saved sessions, most recent first
+#[tauri::command]
+fn list_sessions(app: tauri::AppHandle) -> Result<Vec<SessionSummary>, String> {
+    let root = app.path().app_data_dir().map_err(|e| e.to_string())?.join("sessions");
+    let entries = match fs::read_dir(&root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut sessions: Vec<SessionSummary> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let json = fs::read_to_string(entry.path().join(SESSION_FILE)).ok()?;
+            let session: SavedSession = serde_json::from_str(&json).ok()?;
+            let manifest = fs::read_to_string(entry.path().join(QUEUE_MANIFEST)).ok();
+            let images = manifest
+                .and_then(|json| serde_json::from_str::<Vec<QueueManifestEntry>>(&json).ok())
+                .map_or(0, |manifest| manifest.len());
+            Some(SessionSummary {
+                name: entry.file_name().to_string_lossy().to_string(),
+                saved_at: session.saved_at,
+                model: session.model,
+                images,
+                turns: session.conversation.len(),
+            })
+        })
+        .collect();
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.saved_at));
+    Ok(sessions)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct QueueAutoCleared {
+    removed: usize,
+    remaining: usize, // Pinned images are kept
+}
+
+// Clear the queue (deleting files) once it has been idle for `seconds`; 0 turns this off.
+// Captures, sends and any other change to the queue restart the countdown.
+#[tauri::command]
+fn set_queue_idle_timeout(seconds: u64, app: tauri::AppHandle, tasks: tauri::State<'_, BackgroundTasks>) -> bool {
+    if seconds == 0 {
+        return tasks.stop("queue-idle");
+    }
+    let timeout = Duration::from_secs(seconds);
+    let handle = tauri::async_runtime::spawn(async move {
+        let queue = app.state::<ImageQueue>();
+        let mut snapshot = queue.images.lock().unwrap().clone();
+        let mut idle_since = Instant::now();
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let images = queue.images.lock().unwrap().clone();
+            if images != snapshot {
+                snapshot = images.clone();
+                idle_since = Instant::now();
+            }
+            let last_activity = *queue.last_activity.lock().unwrap();
+            let idle_since = idle_since.max(last_activity);
+            if images.is_empty() || idle_since.elapsed() < timeout {
+                continue;
+            }
+            let paths = images.into_iter().collect::<Vec<String>>();
+            remove_sent_images(&queue, &app.state::<Conversation>(), &paths);
+            let remaining = queue.images.lock().unwrap().len();
+            if remaining < paths.len() {
+                let _ = app.emit("queue-auto-cleared", QueueAutoCleared { removed: paths.len() - remaining, remaining });
+            }
+            // The pinned remainder starts a fresh countdown rather than being re-checked every second
+            snapshot = queue.images.lock().unwrap().clone();
+            *queue.last_activity.lock().unwrap() = Instant::now();
+        }
+    });
+    tasks.track("queue-idle", handle);
+    true
+}
+
+#[tauri::command]
+fn get_queue_length(queue: tauri::State<'_, ImageQueue>) -> usize {
+    let images = queue.images.lock().unwrap();
+    images.len()
+}
+
+#[derive(serde::Serialize)]
+struct QueuedImage {
+    path: String,
+    window_title: Option<String>,
+    caption: Option<String>,
+    pinned: bool,
+}
+
+// The queue in send order with each image's metadata, for the thumbnail strip
+#[tauri::command]
+fn get_queue_images(queue: tauri::State<'_, ImageQueue>) -> Vec<QueuedImage> {
+    let images = queue.images.lock().unwrap().clone();
+    let titles = queue.window_titles.lock().unwrap();
+    let captions = queue.captions.lock().unwrap();
+    let pinned = queue.pinned.lock().unwrap();
+    images
+        .into_iter()
+        .map(|path| QueuedImage {
+            window_title: titles.get(&path).cloned(),
+            caption: captions.get(&path).cloned(),
+            pinned: pinned.contains(&path),
+            path,
+        })
+        .collect()
+}
+
+#[tauri::command]
+fn clear_queue(queue: tauri::State<'_, ImageQueue>) -> usize {
+    // Pinned images survive a clear, along with their captions and group membership
+    let pinned = queue.pinned.lock().unwrap().clone();
+    let mut images = queue.images.lock().unwrap();
+    images.retain(|path| pinned.contains(path));
+    queue.captions.lock().unwrap().retain(|path, _| pinned.contains(path));
+    queue.window_titles.lock().unwrap().retain(|path, _| pinned.contains(path));
+    // Groups outlive a clear but lose their unpinned members
+    for members in queue.groups.lock().unwrap().values_mut() {
+        members.retain(|path| pinned.contains(path));
+    }
+    images.len()
+}
+
+// Remove only the images that were sent, so captures taken mid-request stay queued.
+// Pinned images stay queued and keep their files; files the last request replays are deleted once it is replaced.
+fn remove_sent_images(queue: &ImageQueue, conv: &Conversation, sent: &[String]) {
+    let pinned = queue.pinned.lock().unwrap().clone();
+    let sent: Vec<&String> = sent.iter().filter(|path| !pinned.contains(*path)).collect();
+    queue.images.lock().unwrap().retain(|path| !sent.contains(&path));
+    let mut captions = queue.captions.lock().unwrap();
+    let mut titles = queue.window_titles.lock().unwrap();
+    let replayed = conv.last_request.lock().unwrap().as_ref().map(|last| last.image_paths.clone()).unwrap_or_default();
+    let mut retired = conv.retired_images.lock().unwrap();
+    for path in &sent {
+        captions.remove(*path);
+        titles.remove(*path);
+        if replayed.contains(*path) {
+            retired.insert(path.to_string());
+        } else {
+            let _ = fs::remove_file(path);
+        }
+    }
+    for members in queue.groups.lock().unwrap().values_mut() {
+        members.retain(|path| !sent.contains(&path));
+    }
+}
+
+fn set_queue_pin(queue: &ImageQueue, index: usize, pinned: bool) -> Result<(), String> {
+    let path = queue
+        .images
+        .lock()
+        .unwrap()
+        .get(index)
+        .cloned()
+        .ok_or_else(|| format!("No queued image at index {}", index))?;
+    let mut pins = queue.pinned.lock().unwrap();
+    if pinned {
+        pins.insert(path);
+    } else {
+        pins.remove(&path);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn pin_queue_image(index: usize, queue: tauri::State<'_, ImageQueue>) -> Result<(), String> {
+    set_queue_pin(&queue, index, true)
+}
+
+#[tauri::command]
+fn unpin_queue_image(index: usize, queue: tauri::State<'_, ImageQueue>) -> Result<(), String> {
+    set_queue_pin(&queue, index, false)
+}
+
+#[tauri::command]
+fn set_auto_clear_after_send(enabled: bool, queue: tauri::State<'_, ImageQueue>) -> bool {
+    queue.auto_clear.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+// With auto_chunk, a queue rejected for exceeding the token limit is retried in chunks and the partial answers merged
+#[tauri::command]
+async fn call_gemini_with_image_queue(
+    prompt: String,
+    auto_chunk: Option<bool>,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+    conv: tauri::State<'_, Conversation>,
+) -> Result<String, String> {
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+
+    // Collect image paths and release the lock before async operations
+    let image_paths = {
+        let images = queue.images.lock().unwrap();
+        if images.is_empty() {
+            return Err("No images in queue".to_string());
+        }
+        images.iter().cloned().collect::<Vec<String>>()
+    };
+    if !auto_chunk.unwrap_or(false) {
+        return ask_with_images(prompt, image_paths, &queue, &cfg, &conv).await;
+    }
+    match ask_with_images(prompt.clone(), image_paths.clone(), &queue, &cfg, &conv).await {
+        Err(e) if is_token_limit_error(&e) && image_paths.len() > 1 => ask_in_chunks(prompt, image_paths, &queue, &cfg, &conv).await,
+        result => result,
+    }
+}
+
+fn is_token_limit_error(error: &str) -> bool {
+    let error = error.to_lowercase();
+    (error.contains("token") && (error.contains("limit") || error.contains("exceed") || error.contains("maximum")))
+        || error.contains("too large")
+        || error.contains("request payload size")
+}
+
+const CHUNK_IMAGES: usize = 6;
+
+// Answer each slice of the queue separately, then merge the partial answers with one summarization call
+async fn ask_in_chunks(prompt: String, image_paths: Vec<String>, queue: &ImageQueue, cfg: &AppConfig, conv: &Conversation) -> Result<String, String> {
+    let _slot = request_slot(cfg)?;
+    let client = gemini_client(cfg)?;
+    let model = cfg.model.lock().map_err(|_| "Lock poisoned")?.clone();
+
+    // At least two chunks, since the whole queue was just rejected
+    let chunk_count = image_paths.len().div_ceil(CHUNK_IMAGES).max(2);
+    let chunk_size = image_paths.len().div_ceil(chunk_count);
+    let chunks: Vec<&[String]> = image_paths.chunks(chunk_size).collect();
+
+    let mut partials = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let chunk_prompt = format!(
+            "{}\n\n(These images are part {} of {} of a larger set; answer from what they show.)",
+            prompt,
+            index + 1,
+            chunks.len()
+        );
+        let chat_req = queue_request(&chunk_prompt, chunk, queue, cfg, &model).await?;
+        let res = exec_chat(cfg, &client, &model, chat_req, None)
+            .await
+            .map_err(|e| format!("Chunk {} of {} failed: {}", index + 1, chunks.len(), api_error(&client.pin_failure, e)))?;
+        partials.push(res.content_text_as_str().unwrap_or("[No response]").to_string());
+    }
+
+    let merged_parts = partials
+        .iter()
+        .enumerate()
+        .map(|(index, partial)| format!("--- Part {} ---\n{}", index + 1, partial))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let merge_req = ChatRequest::new(vec![
+        ChatMessage::system(system_prompt(cfg, &model, "Combine partial answers about consecutive parts of one set of screenshots into a single coherent answer. Remove repetition; keep every distinct detail.")),
+        ChatMessage::user(format!("Original request: {}\n\n{}", prompt, merged_parts)),
+    ]);
+    let res = exec_chat(cfg, &client, &model, merge_req, None)
+        .await
+        .map_err(|e| format!("Merging chunk answers failed: {}", api_error(&client.pin_failure, e)))?;
+    let answer = format!(
+        "> **Note:** The queue was too large for one request; answered in {} chunks.\n\n{}",
+        chunks.len(),
+        res.content_text_as_str().unwrap_or("[No response]")
+    );
+
+    if queue.auto_clear.load(Ordering::SeqCst) {
+        remove_sent_images(queue, conv, &image_paths);
+    }
+    record_exchange(cfg, conv, &prompt, &answer);
+    Ok(answer)
+}
+
+// Send queued images (with their captions) in one request, shared by whole-queue and group sends
+async fn ask_with_images(prompt: String, image_paths: Vec<String>, queue: &ImageQueue, cfg: &AppConfig, conv: &Conversation) -> Result<String, String> {
+    let _slot = request_slot(cfg)?;
+    ensure_no_video_call(cfg)?;
+    let client = gemini_client(cfg)?;
+    let model = model_for_request(cfg, &prompt, &image_paths)?;
+    let chat_req = queue_request(&prompt, &image_paths, queue, cfg, &model).await?;
+
+    let res = exec_chat(cfg, &client, &model, chat_req.clone(), None)
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+    remember_response_meta(conv, &res);
+
+    let answer = res
+        .content_text_as_str()
+        .unwrap_or("[No response]")
+        .to_string();
+    let answer = continue_if_truncated(&client, &model, chat_req, answer, cfg, conv).await;
+    record_exchange(cfg, conv, &prompt, &answer);
+    // Recorded first so auto-clear keeps the files rerun_last_with_model and save_to_history replay
+    remember_request(conv, RecordedRequest {
+        mode: "queue".to_string(),
+        system: QUEUE_SYSTEM.to_string(),
+        prompt,
+        image_paths: image_paths.clone(),
+        model,
+        answer: answer.clone(),
+    });
+    if queue.auto_clear.load(Ordering::SeqCst) {
+        remove_sent_images(queue, conv, &image_paths);
+    }
+    Ok(answer)
+}
+
+const QUEUE_SYSTEM: &str = "Be concise and helpful. Analyze all provided images in order.";
+
+// Prompt plus every image as one chat request for `model`
+async fn queue_request(prompt: &str, image_paths: &[String], queue: &ImageQueue, cfg: &AppConfig, model: &str) -> Result<ChatRequest, String> {
+    let message = queue_message(prompt, image_paths, queue, cfg, model_supports_vision(model)).await?;
+    Ok(ChatRequest::new(vec![ChatMessage::system(system_prompt(cfg, model, QUEUE_SYSTEM)), message]))
+}
+
+// The user message for a queue send: the prompt plus every image (preceded by its caption, if any).
+// Without vision the images are sent as their OCR text instead.
+async fn queue_message(prompt: &str, image_paths: &[String], queue: &ImageQueue, cfg: &AppConfig, vision: bool) -> Result<ChatMessage, String> {
+    *queue.last_activity.lock().unwrap() = Instant::now();
+    let captions = queue.captions.lock().unwrap().clone();
+    let titles = queue.window_titles.lock().unwrap().clone();
+    let mut content_parts = Vec::new();
+
+    // Add all images from the queue, each preceded by its source window and caption when known
+    for image_path in image_paths.iter() {
+        if let Some(title) = titles.get(image_path) {
+            content_parts.push(ContentPart::from_text(format!("The following image was captured from the window \"{}\".", title)));
+        }
+        if let Some(caption) = captions.get(image_path) {
+            content_parts.push(ContentPart::from_text(caption.clone()));
+        }
+        content_parts.push(image_content(cfg, image_path, vision).await?);
+    }
+
+    Ok(ChatMessage::user(order_parts(cfg, ContentPart::from_text(prompt), content_parts)))
+}
+
+// The prompt and image parts (captions included) in the order chosen by set_content_order
+fn order_parts(cfg: &AppConfig, prompt: ContentPart, mut images: Vec<ContentPart>) -> Vec<ContentPart> {
+    if cfg.images_first.load(Ordering::SeqCst) {
+        images.push(prompt);
+    } else {
+        images.insert(0, prompt);
+    }
+    images
+}
+
+// "text-first" (default) or "images-first": where the prompt goes relative to the images in
+// single-image, queue and rerun requests; some models answer better when the question comes last
+#[tauri::command]
+fn set_content_order(order: String, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let images_first = match order.as_str() {
+        "text-first" => false,
+        "images-first" => true,
+        other => return Err(format!("Unknown content order '{}'; use text-first or images-first", other)),
+    };
+    cfg.images_first.store(images_first, Ordering::SeqCst);
+    Ok(order)
+}
+
+#[derive(serde::Serialize)]
+struct ModelComparison {
+    model: String,
+    answer: Option<String>,
+    error: Option<String>,
+    latency_ms: u64,
+    prompt_tokens: Option<i32>,
+    completion_tokens: Option<i32>,
+    total_tokens: Option<i32>,
+}
+
+const MAX_COMPARED_MODELS: usize = 6;
+const COMPARE_CONCURRENCY: usize = 3;
+
+// Run the same queue against each model (a few at a time) and report answers side by side.
+// A benchmark only: the queue is kept and nothing is added to the conversation.
+#[tauri::command]
+async fn compare_models(
+    prompt: String,
+    models: Vec<String>,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<Vec<ModelComparison>, String> {
+    use futures::StreamExt;
+
+    if models.is_empty() {
+        return Err("No models to compare".to_string());
+    }
+    if models.len() > MAX_COMPARED_MODELS {
+        return Err(format!("Compare at most {} models at a time", MAX_COMPARED_MODELS));
+    }
+    let image_paths = {
+        let images = queue.images.lock().unwrap();
+        if images.is_empty() {
+            return Err("No images in queue".to_string());
+        }
+        images.iter().cloned().collect::<Vec<String>>()
+    };
+
+    let _slot = request_slot(&cfg)?;
+    // Each model running at once holds its own request slot, so comparisons stay within the configured limit
+    let slots = cfg.request_slots.lock().map_err(|_| "Lock poisoned")?.1.clone();
+    let extra_slots: Vec<_> = (1..COMPARE_CONCURRENCY).map_while(|_| slots.clone().try_acquire_owned().ok()).collect();
+    let client = gemini_client(&cfg)?;
+    // Text-only models get the queue as OCR text; build each variant at most once
+    let vision_message = match models.iter().any(|model| model_supports_vision(model)) {
+        true => Some(queue_message(&prompt, &image_paths, &queue, &cfg, true).await?),
+        false => None,
+    };
+    let text_message = match models.iter().any(|model| !model_supports_vision(model)) {
+        true => Some(queue_message(&prompt, &image_paths, &queue, &cfg, false).await?),
+        false => None,
+    };
+
+    let runs = models.into_iter().map(|model| {
+        let message = if model_supports_vision(&model) { vision_message.clone() } else { text_message.clone() };
+        let chat_req = ChatRequest::new(vec![
+            ChatMessage::system(system_prompt(&cfg, &model, QUEUE_SYSTEM)),
+            message.unwrap_or_else(|| ChatMessage::user(prompt.clone())),
+        ]);
+        let (client, cfg) = (&client, cfg.inner());
+        async move {
+            let started = Instant::now();
+            let result = exec_chat(cfg, client, &model, chat_req, None).await;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            match result {
+                Ok(res) => ModelComparison {
+                    answer: Some(res.content_text_as_str().unwrap_or("[No response]").to_string()),
+                    error: None,
+                    latency_ms,
+                    prompt_tokens: res.usage.prompt_tokens,
+                    completion_tokens: res.usage.completion_tokens,
+                    total_tokens: res.usage.total_tokens,
+                    model,
+                },
+                Err(e) => ModelComparison {
+                    answer: None,
+                    error: Some(api_error(&client.pin_failure, e)),
+                    latency_ms,
+                    prompt_tokens: None,
+                    completion_tokens: None,
+                    total_tokens: None,
+                    model,
+                },
+            }
+        }
+    });
+    Ok(futures::stream::iter(runs).buffered(1 + extra_slots.len()).collect().await)
+}
+
+// Await `fut` unless the deadline (if any) passes first, in which case None
+async fn until_deadline<F: std::future::Future>(deadline: Option<tokio::time::Instant>, fut: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+// Longest wait before streamed text is pushed to the overlay; 0 emits every chunk as it arrives
+#[tauri::command]
+fn set_stream_flush_interval(ms: u32, cfg: tauri::State<'_, AppConfig>) -> Result<u32, String> {
+    if ms > 1000 {
+        return Err("Flush interval must be at most 1000 ms".to_string());
+    }
+    cfg.stream_flush_ms.store(ms, Ordering::SeqCst);
+    Ok(ms)
+}
+
+#[derive(serde::Serialize)]
+struct StreamedAnswer {
+    text: String,
+    partial: bool, // True when the deadline cut the stream short
+}
+
+// Stream the queue answer as "answer-chunk" events. With deadline_ms the call stops at the deadline
+// and returns whatever text has arrived, flagged partial; the queue is kept so it can be retried.
+#[tauri::command]
+async fn call_gemini_stream(
+    prompt: String,
+    deadline_ms: Option<u64>,
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+    conv: tauri::State<'_, Conversation>,
+) -> Result<StreamedAnswer, String> {
+    use futures::StreamExt;
+    use genai::chat::ChatStreamEvent;
+
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+    let _slot = request_slot(&cfg)?;
+    ensure_no_video_call(&cfg)?;
+    let deadline = deadline_ms.map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
+
+    let image_paths = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    if image_paths.is_empty() {
+        return Err("No images in queue".to_string());
+    }
+    let client = gemini_client(&cfg)?;
+    let model = model_for_request(&cfg, &prompt, &image_paths)?;
+    let chat_req = queue_request(&prompt, &image_paths, &queue, &cfg, &model).await?;
+
+    // Chunks are coalesced into one "answer-chunk" per flush interval so fast streams don't flood the webview
+    let flush_interval = Duration::from_millis(cfg.stream_flush_ms.load(Ordering::SeqCst) as u64);
+    let mut text = String::new();
+    let mut pending = String::new();
+    let mut last_flush = tokio::time::Instant::now();
+    let mut partial = true;
+    if let Some(response) = until_deadline(deadline, client.exec_chat_stream(&model, chat_req, None)).await {
+        let mut stream = response
+            .map_err(|e| api_error(&client.pin_failure, e))
+            .inspect_err(|e| note_quota_error(&cfg, e))?
+            .stream;
+        loop {
+            let next = until_deadline(deadline, stream.next());
+            // With text waiting, don't sit on it past the flush time if the stream stalls
+            let event = if pending.is_empty() {
+                next.await
+            } else {
+                match tokio::time::timeout_at(last_flush + flush_interval, next).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        let _ = app.emit("answer-chunk", std::mem::take(&mut pending));
+                        last_flush = tokio::time::Instant::now();
+                        continue;
+                    }
+                }
+            };
+            let Some(event) = event else {
+                break; // Deadline
+            };
+            match event {
+                Some(Ok(ChatStreamEvent::Chunk(chunk))) => {
+                    text.push_str(&chunk.content);
+                    pending.push_str(&chunk.content);
+                    if last_flush.elapsed() >= flush_interval {
+                        let _ = app.emit("answer-chunk", std::mem::take(&mut pending));
+                        last_flush = tokio::time::Instant::now();
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    if !pending.is_empty() {
+                        let _ = app.emit("answer-chunk", std::mem::take(&mut pending));
+                    }
+                    note_quota_error(&cfg, &e.to_string());
+                    return Err(e.to_string());
+                }
+                None => {
+                    partial = false;
+                    break;
+                }
+            }
+        }
+    }
+    if !pending.is_empty() {
+        let _ = app.emit("answer-chunk", pending);
+    }
+    // Streams don't report usage here, so the tokens are estimated
+    track_quota(&cfg, (approximate_tokens(&prompt) + approximate_tokens(&text)) as u64);
+    if partial && text.is_empty() {
+        return Err("Deadline reached before any answer arrived".to_string());
+    }
+
+    record_exchange(&cfg, &conv, &prompt, &text);
+    // A cut-off answer isn't kept as the replayable request, and its images stay queued
+    if !partial {
+        remember_request(&conv, RecordedRequest {
+            mode: "queue".to_string(),
+            system: QUEUE_SYSTEM.to_string(),
+            prompt,
+            image_paths: image_paths.clone(),
+            model,
+            answer: text.clone(),
+        });
+        if queue.auto_clear.load(Ordering::SeqCst) {
+            remove_sent_images(&queue, &conv, &image_paths);
+        }
+    }
+    Ok(StreamedAnswer { text, partial })
+}
+
+#[tauri::command]
+fn create_group(name: String, queue: tauri::State<'_, ImageQueue>) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Group name cannot be empty".to_string());
+    }
+    queue.groups.lock().unwrap().entry(name).or_default();
+    Ok(())
+}
+
+// Membership follows the image itself, so it survives the queue being reordered
+#[tauri::command]
+fn assign_to_group(index: usize, name: String, queue: tauri::State<'_, ImageQueue>) -> Result<usize, String> {
+    let path = queue
+        .images
+        .lock()
+        .unwrap()
+        .get(index)
+        .cloned()
+        .ok_or_else(|| format!("No queued image at index {}", index))?;
+    let mut groups = queue.groups.lock().unwrap();
+    let members = groups.get_mut(&name).ok_or_else(|| format!("No group named '{}'", name))?;
+    if !members.contains(&path) {
+        members.push(path);
+    }
+    Ok(members.len())
+}
+
+#[tauri::command]
+fn list_groups(queue: tauri::State<'_, ImageQueue>) -> HashMap<String, Vec<String>> {
+    queue.groups.lock().unwrap().clone()
+}
+
+#[tauri::command]
+async fn call_gemini_with_group(name: String, prompt: String, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>, conv: tauri::State<'_, Conversation>) -> Result<String, String> {
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+
+    // Send the group's images in current queue order
+    let members = queue
+        .groups
+        .lock()
+        .unwrap()
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("No group named '{}'", name))?;
+    let image_paths = queue
+        .images
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|path| members.contains(path))
+        .cloned()
+        .collect::<Vec<String>>();
+    if image_paths.is_empty() {
+        return Err(format!("Group '{}' has no queued images", name));
+    }
+    ask_with_images(prompt, image_paths, &queue, &cfg, &conv).await
+}
+
+#[derive(serde::Serialize)]
+struct ExtractedTable {
+    title: Option<String>,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+#[derive(serde::Serialize)]
+struct TableExtraction {
+    tables: Vec<ExtractedTable>,
+    raw: Option<String>, // Set when the model's reply wasn't valid table JSON
+}
+
+// Models often wrap JSON in a ```json fence even when asked not to
+fn strip_code_fence(text: &str) -> &str {
+    let trimmed = text.trim();
+    match trimmed.strip_prefix("```") {
+        Some(rest) => {
+            let rest = rest.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+            rest.strip_suffix("```").unwrap_or(rest).trim()
+        }
+        None => trimmed,
+    }
+}
+
+fn parse_tables(text: &str) -> Option<Vec<ExtractedTable>> {
+    let cell = |v: &serde_json::Value| match v {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    let json: serde_json::Value = serde_json::from_str(strip_code_fence(text)).ok()?;
+    let tables = json["tables"].as_array()?;
+    Some(
+        tables
+            .iter()
+            .map(|t| ExtractedTable {
+                title: t["title"].as_str().map(|s| s.to_string()),
+                headers: t["headers"].as_array().map(|h| h.iter().map(cell).collect()).unwrap_or_default(),
+                rows: t["rows"]
+                    .as_array()
+                    .map(|rows| {
+                        rows.iter()
+                            .filter_map(|r| r.as_array().map(|r| r.iter().map(cell).collect()))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect(),
+    )
+}
+
+#[tauri::command]
+async fn extract_tables(queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<TableExtraction, String> {
+    let _slot = request_slot(&cfg)?;
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+
+    let image_paths = {
+        let images = queue.images.lock().unwrap();
+        if images.is_empty() {
+            return Err("No images in queue".to_string());
+        }
+        images.iter().cloned().collect::<Vec<String>>()
+    };
+
+    let mut content_parts = vec![ContentPart::from_text(
+        "Find every table in the screenshot(s), such as constraints or input/output examples. \
+         Reply with JSON only, in the form {\"tables\": [{\"title\": string or null, \"headers\": [string], \"rows\": [[string]]}]}. \
+         Use {\"tables\": []} if there are none.",
+    )];
+    let model = cfg.model.lock().map_err(|_| "Lock poisoned")?.clone();
+    for image_path in image_paths.iter() {
+        content_parts.push(image_content(&cfg, image_path, model_supports_vision(&model)).await?);
+    }
+
+    let client = gemini_client(&cfg)?;
+    let chat_req = ChatRequest::new(vec![
+        ChatMessage::system("You convert tables in images into structured JSON exactly as shown."),
+        ChatMessage::user(content_parts),
+    ]);
+    let options = ChatOptions::default().with_response_format(ChatResponseFormat::JsonMode);
+
+    let res = exec_chat(&cfg, &client, &model, chat_req, Some(&options))
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+
+    let text = res.content_text_as_str().unwrap_or("").to_string();
+    Ok(match parse_tables(&text) {
+        Some(tables) => TableExtraction { tables, raw: None },
+        None => TableExtraction { tables: Vec::new(), raw: Some(text) },
+    })
+}
+
+// Keyword evidence for each problem type; the highest count wins
+const PROBLEM_TYPE_HINTS: &[(&str, &[&str])] = &[
+    ("mcq", &["which of the following", "select all", "choose the", "all that apply", "(a)", "option a", "true or false"]),
+    ("coding", &["input", "output", "constraints", "example 1", "return", "function", "class solution", "def ", "int main", "o(n"]),
+    ("system-design", &["design a", "system design", "scalab", "million users", "high availability", "load balanc", "throughput", "latency", "sharding"]),
+    ("behavioral", &["tell me about a time", "describe a situation", "conflict", "weakness", "strength", "why do you want", "challenge you faced", "teammate"]),
+];
+
+fn classify_problem(text: &str) -> &'static str {
+    let text = text.to_lowercase();
+    PROBLEM_TYPE_HINTS
+        .iter()
+        .map(|(kind, hints)| (*kind, hints.iter().filter(|hint| text.contains(*hint)).count()))
+        .filter(|(_, score)| *score > 0)
+        // max_by_key keeps the last maximum; reverse so earlier (more specific) types win ties
+        .rev()
+        .max_by_key(|(_, score)| *score)
+        .map(|(kind, _)| kind)
+        .unwrap_or("unknown")
+}
+
+// Frontend prompt preset matching a problem type; design and behavioral questions have none yet
+fn preset_for_problem(kind: &str, text: &str) -> Option<&'static str> {
+    let text = text.to_lowercase();
+    match kind {
+        "mcq" if ["select all", "all that apply", "one or more", "multiple correct"].iter().any(|hint| text.contains(hint)) => {
+            Some("multiple-correct-mcq")
+        }
+        "mcq" => Some("single-correct-mcq"),
+        "coding" => Some("code-without-comments"),
+        _ => None,
+    }
+}
+
+// UI text that identifies each coding platform; the highest count wins
+const PLATFORM_HINTS: &[(&str, &[&str])] = &[
+    ("leetcode", &["leetcode", "class solution", "testcase", "acceptance rate", "daily question"]),
+    ("hackerrank", &["hackerrank", "complete the function", "sample input 0", "sample output 0", "stdin"]),
+    ("codility", &["codility", "write a function", "def solution(", "int solution(", "assume that"]),
+    ("codesignal", &["codesignal", "[execution time limit]", "[input]", "[output]"]),
+    ("codeforces", &["codeforces", "time limit per test", "memory limit per test", "standard input"]),
+];
+
+fn classify_platform(text: &str) -> Option<&'static str> {
+    let text = text.to_lowercase();
+    PLATFORM_HINTS
+        .iter()
+        .map(|(platform, hints)| (*platform, hints.iter().filter(|hint| text.contains(*hint)).count()))
+        .filter(|(_, score)| *score > 0)
+        .rev()
+        .max_by_key(|(_, score)| *score)
+        .map(|(platform, _)| platform)
+}
+
+// Prompt instruction for how a platform feeds input to solutions
+fn platform_io_convention(platform: &str) -> Option<&'static str> {
+    match platform {
+        "leetcode" | "codility" | "codesignal" => Some(
+            "The problem is on a function-body platform: complete the given function or class signature and return the result. Do not read stdin, print output, or add a main function.",
+        ),
+        "hackerrank" => Some(
+            "The problem is on HackerRank: if a function stub is shown, complete only that function; otherwise read input from stdin and print to stdout.",
+        ),
+        "codeforces" => Some("The problem reads from standard input: write a full program that reads stdin and prints the answer to stdout."),
+        _ => None,
+    }
+}
+
+#[derive(serde::Serialize)]
+struct PlatformDetection {
+    platform: Option<&'static str>, // None when no known platform was recognized
+    io_convention: Option<&'static str>,
+}
+
+// OCR the queue locally, recognize the coding platform and make its I/O convention part of every prompt
+#[tauri::command]
+async fn detect_coding_platform(
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<PlatformDetection, String> {
+    let image_paths = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    if image_paths.is_empty() {
+        return Err("No images in queue".to_string());
+    }
+    let text = ocr_images_with_progress(&app, &cfg, &image_paths).await?;
+    let platform = classify_platform(&text);
+    *cfg.platform_hint.lock().map_err(|_| "Lock poisoned")? = platform;
+    Ok(PlatformDetection { platform, io_convention: platform.and_then(platform_io_convention) })
+}
+
+// Set (or with None clear) the platform hint by hand
+#[tauri::command]
+fn set_platform_hint(platform: Option<String>, cfg: tauri::State<'_, AppConfig>) -> Result<PlatformDetection, String> {
+    let platform = match platform {
+        Some(name) => Some(
+            PLATFORM_HINTS
+                .iter()
+                .map(|(known, _)| *known)
+                .find(|known| known.eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| format!("Unknown platform '{}'", name))?,
+        ),
+        None => None,
+    };
+    *cfg.platform_hint.lock().map_err(|_| "Lock poisoned")? = platform;
+    Ok(PlatformDetection { platform, io_convention: platform.and_then(platform_io_convention) })
+}
+
+#[derive(serde::Serialize)]
+struct ProblemDetection {
+    problem_type: &'static str, // "mcq", "coding", "system-design", "behavioral" or "unknown"
+    preset: Option<&'static str>,
+    extracted_text: String,
+}
+
+// Transcribe the queue with a cheap model, then classify the text by keyword heuristics
+#[tauri::command]
+async fn detect_problem_type(queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<ProblemDetection, String> {
+    let _slot = request_slot(&cfg)?;
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+    let image_paths = queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>();
+    if image_paths.is_empty() {
+        return Err("No images in queue".to_string());
+    }
+
+    let mut content_parts = vec![ContentPart::from_text("Transcribe all visible text in the screenshot(s) verbatim. Output only the text.")];
+    for image_path in image_paths.iter() {
+        content_parts.push(encode_image(&cfg, image_path).await?);
+    }
+    let client = gemini_client(&cfg)?;
+    let res = exec_chat(&cfg, &client, DETECTION_MODEL, ChatRequest::new(vec![ChatMessage::user(content_parts)]), None)
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+
+    let extracted_text = res.content_text_as_str().unwrap_or("").to_string();
+    let problem_type = classify_problem(&extracted_text);
+    Ok(ProblemDetection {
+        problem_type,
+        preset: preset_for_problem(problem_type, &extracted_text),
+        extracted_text,
+    })
+}
+
+#[tauri::command]
+async fn call_gemini_smart(prompt: String, app: tauri::AppHandle, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>, conv: tauri::State<'_, Conversation>) -> Result<String, String> {
+    let has_queued = !queue.images.lock().unwrap().is_empty();
+    if has_queued {
+        return call_gemini_with_image_queue(prompt, None, queue, cfg, conv).await;
+    }
+
+    // Nothing queued: send a fresh capture of the whole screen instead
+    let image_path = capture_full_screen(app, cfg.clone()).await?;
+    call_gemini_with_image(prompt, image_path, cfg, conv).await
+}
+
+// Drop exchanges whose answer repeats the previous answer verbatim; returns how many turns were removed
+fn dedupe_turns(turns: &mut Vec<ConversationTurn>) -> usize {
+    let before = turns.len();
+    let mut kept: Vec<ConversationTurn> = Vec::with_capacity(turns.len());
+    for turn in turns.drain(..) {
+        if turn.role == "assistant" {
+            let last_answer = kept.iter().rev().find(|t| t.role == "assistant");
+            if last_answer.is_some_and(|a| a.text.trim() == turn.text.trim()) {
+                // Also drop the question that produced the repeated answer
+                if kept.last().is_some_and(|t| t.role == "user") {
+                    kept.pop();
+                }
+                continue;
             }
-            return Some(key);
         }
+        kept.push(turn);
     }
-    
-    // If not in environment, try AppConfig
-    if let Ok(guard) = cfg.api_key.lock() {
-        if let Some(key) = guard.clone() {
-            return Some(key);
+    *turns = kept;
+    before - turns.len()
+}
+
+fn record_exchange(cfg: &AppConfig, conv: &Conversation, prompt: &str, answer: &str) {
+    record_event(cfg, "prompt", json!({ "prompt": prompt }));
+    record_event(cfg, "answer", json!({ "answer": answer }));
+    let mut turns = conv.turns.lock().unwrap();
+    turns.push(ConversationTurn { role: "user".to_string(), text: prompt.to_string() });
+    turns.push(ConversationTurn { role: "assistant".to_string(), text: answer.to_string() });
+    if conv.dedupe.load(Ordering::SeqCst) {
+        dedupe_turns(&mut turns);
+    }
+}
+
+fn remember_request(conv: &Conversation, request: RecordedRequest) {
+    // Auto-cleared files only the replaced request still needed can go now
+    conv.retired_images.lock().unwrap().retain(|path| {
+        let keep = request.image_paths.contains(path);
+        if !keep {
+            let _ = fs::remove_file(path);
         }
+        keep
+    });
+    *conv.last_request.lock().unwrap() = Some(request);
+}
+
+// What genai reports beyond the answer text. genai 0.3 does not surface finish reasons or safety ratings.
+#[derive(Clone, serde::Serialize)]
+struct ResponseMeta {
+    adapter: String,
+    requested_model: String,
+    provider_model: String, // The exact version the provider says it used
+    usage: genai::chat::Usage,
+    has_reasoning: bool,
+    tool_calls: usize,
+    received_at: u64,
+    continuations: u32, // Follow-ups sent because the answer looked cut off, see set_auto_continue
+}
+
+fn remember_response_meta(conv: &Conversation, res: &genai::chat::ChatResponse) {
+    *conv.last_response_meta.lock().unwrap() = Some(ResponseMeta {
+        adapter: res.model_iden.adapter_kind.as_lower_str().to_string(),
+        requested_model: res.model_iden.model_name.to_string(),
+        provider_model: res.provider_model_iden.model_name.to_string(),
+        usage: res.usage.clone(),
+        has_reasoning: res.reasoning_content.is_some(),
+        tool_calls: res.tool_calls().map_or(0, |calls| calls.len()),
+        received_at: unix_now(),
+        continuations: 0,
+    });
+}
+
+// Share of a daily limit at which "quota-warning" fires
+const QUOTA_WARN_SHARE: f64 = 0.9;
+
+// Gemini quotas reset at midnight Pacific time; standard time is close enough for a warning
+fn quota_day() -> u64 {
+    unix_now().saturating_sub(8 * 3600) / 86_400
+}
+
+fn roll_quota_day(quota: &mut QuotaUsage) {
+    let today = quota_day();
+    if quota.day != today {
+        *quota = QuotaUsage { day: today, ..QuotaUsage::default() };
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+struct QuotaStatus {
+    requests: u32,
+    tokens: u64,
+    requests_per_day: Option<u32>,
+    tokens_per_day: Option<u64>,
+    remaining_requests: Option<u32>, // Only when a daily limit is set
+    remaining_tokens: Option<u64>,
+    exhausted: bool,
+    near_limit: bool,
+    resets_at: u64, // Unix seconds
+}
+
+fn quota_status(usage: &QuotaUsage, limits: &QuotaLimits) -> QuotaStatus {
+    let near = |used: f64, limit: f64| used >= limit * QUOTA_WARN_SHARE;
+    QuotaStatus {
+        requests: usage.requests,
+        tokens: usage.tokens,
+        requests_per_day: limits.requests_per_day,
+        tokens_per_day: limits.tokens_per_day,
+        remaining_requests: limits.requests_per_day.map(|limit| limit.saturating_sub(usage.requests)),
+        remaining_tokens: limits.tokens_per_day.map(|limit| limit.saturating_sub(usage.tokens)),
+        exhausted: usage.exhausted,
+        near_limit: usage.exhausted
+            || limits.requests_per_day.is_some_and(|limit| near(usage.requests as f64, limit as f64))
+            || limits.tokens_per_day.is_some_and(|limit| near(usage.tokens as f64, limit as f64)),
+        resets_at: (usage.day + 1) * 86_400 + 8 * 3600,
+    }
+}
+
+// Apply a change to today's usage, save it, and send "quota-warning" once when it nears a limit
+// and again whenever the API starts refusing requests
+fn update_quota(cfg: &AppConfig, change: impl FnOnce(&mut QuotaUsage)) {
+    let warning = {
+        let Ok(mut persisted) = cfg.persisted.lock() else {
+            return;
+        };
+        roll_quota_day(&mut persisted.quota_usage);
+        let was_exhausted = persisted.quota_usage.exhausted;
+        change(&mut persisted.quota_usage);
+        let status = quota_status(&persisted.quota_usage, &persisted.quota_limits);
+        let first_warning = status.near_limit && !std::mem::replace(&mut persisted.quota_usage.warned, true);
+        let newly_exhausted = status.exhausted && !was_exhausted;
+        let _ = save_persisted_config(&cfg.app, &persisted);
+        (first_warning || newly_exhausted).then_some(status)
+    };
+    if let Some(status) = warning {
+        let _ = cfg.app.emit("quota-warning", status);
+    }
+}
+
+fn track_quota(cfg: &AppConfig, tokens: u64) {
+    update_quota(cfg, |usage| {
+        usage.requests += 1;
+        usage.tokens += tokens;
+    });
+}
+
+// A RESOURCE_EXHAUSTED / 429 answer means today's quota is gone, whatever the local count says
+fn note_quota_error(cfg: &AppConfig, message: &str) {
+    if message.contains("RESOURCE_EXHAUSTED") || message.contains("Too Many Requests") {
+        update_quota(cfg, |usage| usage.exhausted = true);
     }
-    
-    None
 }
 
+// Every non-streamed generation request goes through here, so the quota count sees all of them
+async fn exec_chat(
+    cfg: &AppConfig,
+    client: &genai::Client,
+    model: &str,
+    chat_req: ChatRequest,
+    options: Option<&ChatOptions>,
+) -> Result<genai::chat::ChatResponse, genai::Error> {
+    let result = client.exec_chat(model, chat_req, options).await;
+    match &result {
+        Ok(res) => track_quota(cfg, res.usage.total_tokens.unwrap_or(0).max(0) as u64),
+        Err(e) => note_quota_error(cfg, &e.to_string()),
+    }
+    result
+}
+
+// Today's usage as counted by this app (every Gemini request, streams estimated) against the configured
+// daily limits. genai doesn't expose response headers, so remaining amounts are derived from set_quota_limits.
 #[tauri::command]
-fn set_model(model: String, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
-    let mut model_guard = cfg.model.lock().map_err(|_| "Lock poisoned")?;
-    *model_guard = model.clone();
-    Ok(model)
+fn get_quota_status(cfg: tauri::State<'_, AppConfig>) -> Result<QuotaStatus, String> {
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    roll_quota_day(&mut persisted.quota_usage);
+    Ok(quota_status(&persisted.quota_usage, &persisted.quota_limits))
 }
 
+// Daily request/token allowances for quota warnings; None removes a limit
 #[tauri::command]
-fn set_hf_token(token: String, cfg: tauri::State<'_, AppConfig>) -> Result<(), String> {
-    // First, check if the environment variable has changed
-    let _env_token = std::env::var("HUGGINGFACE_TOKEN").ok();
-    let mut hf_token_guard = cfg.hf_token.lock().map_err(|_| "Lock poisoned")?;
-    
-    // Check if the provided token is different from what's in AppConfig
-    let current_token = hf_token_guard.clone();
-    let token_changed = current_token != Some(token.clone());
-    
-    // If token is empty, clear everything
-    if token.trim().is_empty() {
-        *hf_token_guard = None;
-        std::env::remove_var("HUGGINGFACE_TOKEN");
+fn set_quota_limits(
+    requests_per_day: Option<u32>,
+    tokens_per_day: Option<u64>,
+    app: tauri::AppHandle,
+    cfg: tauri::State<'_, AppConfig>,
+) -> Result<QuotaStatus, String> {
+    {
+        let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+        persisted.quota_limits = QuotaLimits { requests_per_day, tokens_per_day };
+        persisted.quota_usage.warned = false;
+        save_persisted_config(&app, &persisted)?;
+    }
+    get_quota_status(cfg)
+}
+
+// An answer that stops inside a code block, or mid-sentence on a prose line, was probably cut off by max_tokens
+fn answer_looks_truncated(answer: &str) -> bool {
+    if !is_balanced_fences(answer) {
+        return true;
+    }
+    let Some(last_line) = answer.trim_end().lines().last().map(str::trim) else {
+        return false;
+    };
+    // Lists, headings, tables, quotes and closing fences legitimately end without punctuation
+    let structural = last_line.starts_with(['-', '*', '#', '|', '>', '`']) || last_line.starts_with(|c: char| c.is_ascii_digit());
+    !structural && last_line.ends_with(|c: char| c.is_alphanumeric() || ",;:(-=+".contains(c))
+}
+
+fn is_balanced_fences(text: &str) -> bool {
+    text.lines().filter(|line| line.trim_start().starts_with("```")).count() % 2 == 0
+}
+
+const CONTINUE_PROMPT: &str = "Your previous answer was cut off. Continue exactly where it stopped, without repeating anything. \
+    If it stopped inside a code block, continue the code without opening a new block.";
+
+// With auto-continue on, ask for the rest of a truncated answer (up to the configured number of
+// times) and append it. A failed follow-up keeps what was received so far.
+async fn continue_if_truncated(client: &genai::Client, model: &str, chat_req: ChatRequest, mut answer: String, cfg: &AppConfig, conv: &Conversation) -> String {
+    let limit = cfg.auto_continue.load(Ordering::SeqCst);
+    let mut continuations = 0;
+    while continuations < limit && answer_looks_truncated(&answer) {
+        let follow_up = chat_req.clone().append_message(ChatMessage::assistant(answer.clone())).append_message(ChatMessage::user(CONTINUE_PROMPT));
+        let Ok(res) = exec_chat(cfg, client, model, follow_up, None).await else {
+            break;
+        };
+        let piece = res.content_text_as_str().unwrap_or("");
+        if piece.trim().is_empty() {
+            break;
+        }
+        // Drop a fence the model re-opened despite being told to continue the open block
+        let piece = match piece.trim_start().strip_prefix("```") {
+            Some(rest) if !is_balanced_fences(&answer) => rest.split_once('\n').map_or("", |(_, code)| code),
+            _ => piece,
+        };
+        answer.push_str(piece);
+        continuations += 1;
+    }
+    if continuations > 0 {
+        if let Some(meta) = conv.last_response_meta.lock().unwrap().as_mut() {
+            meta.continuations = continuations;
+        }
+    }
+    answer
+}
+
+// How many "continue" follow-ups a truncated text, image or queue answer may get; 0 turns it off.
+// Whether any were needed shows in get_last_response_meta as `continuations`.
+#[tauri::command]
+fn set_auto_continue(max_continuations: u32, cfg: tauri::State<'_, AppConfig>) -> Result<u32, String> {
+    if max_continuations > 5 {
+        return Err("At most 5 continuations".to_string());
+    }
+    cfg.auto_continue.store(max_continuations, Ordering::SeqCst);
+    Ok(max_continuations)
+}
+
+// Metadata of the most recent answer from the text, image, queue, follow-up or rerun calls
+#[tauri::command]
+fn get_last_response_meta(conv: tauri::State<'_, Conversation>) -> Option<ResponseMeta> {
+    conv.last_response_meta.lock().unwrap().clone()
+}
+
+#[derive(serde::Serialize)]
+struct RerunComparison {
+    mode: String,
+    previous_model: String,
+    previous_answer: String,
+    model: String,
+    answer: String,
+    diff: Vec<DiffLine>, // previous_answer -> answer
+}
+
+#[derive(serde::Serialize)]
+struct DiffLine {
+    tag: &'static str, // "equal", "delete" or "insert"
+    text: String,
+}
+
+fn line_diff(a: &str, b: &str) -> Vec<DiffLine> {
+    similar::TextDiff::from_lines(a, b)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            tag: match change.tag() {
+                similar::ChangeTag::Equal => "equal",
+                similar::ChangeTag::Delete => "delete",
+                similar::ChangeTag::Insert => "insert",
+            },
+            text: change.value().trim_end_matches(['\r', '\n']).to_string(),
+        })
+        .collect()
+}
+
+#[tauri::command]
+fn diff_text(a: String, b: String) -> Vec<DiffLine> {
+    line_diff(&a, &b)
+}
+
+#[tauri::command]
+async fn rerun_last_with_model(model: String, cfg: tauri::State<'_, AppConfig>, conv: tauri::State<'_, Conversation>) -> Result<RerunComparison, String> {
+    let _slot = request_slot(&cfg)?;
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+    }
+
+    let last = conv
+        .last_request
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or("No previous request to rerun")?;
+
+    let user_message = if last.image_paths.is_empty() {
+        ChatMessage::user(last.prompt.clone())
     } else {
-        // Update AppConfig with new token
-        *hf_token_guard = Some(token.clone());
-        
-        // Only update environment variable if token has changed
-        if token_changed {
-            std::env::set_var("HUGGINGFACE_TOKEN", token.clone());
+        let mut content_parts = Vec::new();
+        for image_path in last.image_paths.iter() {
+            content_parts.push(image_content(&cfg, image_path, model_supports_vision(&model)).await?);
         }
+        ChatMessage::user(order_parts(&cfg, ContentPart::from_text(last.prompt.clone()), content_parts))
+    };
+
+    let client = gemini_client(&cfg)?;
+    let chat_req = ChatRequest::new(vec![ChatMessage::system(system_prompt(&cfg, &model, &last.system)), user_message]);
+
+    let res = exec_chat(&cfg, &client, &model, chat_req, None)
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+    remember_response_meta(&conv, &res);
+
+    let answer = res
+        .content_text_as_str()
+        .unwrap_or("[No response]")
+        .to_string();
+    Ok(RerunComparison {
+        mode: last.mode,
+        diff: line_diff(&last.answer, &answer),
+        previous_model: last.model,
+        previous_answer: last.answer,
+        model,
+        answer,
+    })
+}
+
+#[tauri::command]
+async fn call_gemini_follow_up(prompt: String, cfg: tauri::State<'_, AppConfig>, conv: tauri::State<'_, Conversation>) -> Result<String, String> {
+    let _slot = request_slot(&cfg)?;
+    if std::env::var("GEMINI_API_KEY").is_err() {
+        return Err("GEMINI_API_KEY environment variable not set.".to_string());
     }
 
-    // Persist to Windows user environment and broadcast change
-    #[cfg(windows)]
-    {
-        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-        if let Ok(env) = hkcu.open_subkey_with_flags("Environment", winreg::enums::KEY_SET_VALUE) {
-            let token_value = std::env::var("HUGGINGFACE_TOKEN").unwrap_or_default();
-            println!("DEBUG: Writing HUGGINGFACE_TOKEN to registry: {}", &token_value[..std::cmp::min(10, token_value.len())]);
-            if let Err(e) = env.set_value("HUGGINGFACE_TOKEN", &token_value) {
-                eprintln!("Failed to write HUGGINGFACE_TOKEN to registry: {}", e);
-            } else {
-                println!("DEBUG: Successfully wrote HUGGINGFACE_TOKEN to registry");
-            }
+    let model = cfg.model.lock().map_err(|_| "Lock poisoned")?.clone();
+
+    // Replay the recorded history so the model can refer back to earlier answers
+    let mut messages = vec![ChatMessage::system(system_prompt(
+        &cfg,
+        &model,
+        "Be concise and helpful. Continue the conversation using the earlier answers as context.",
+    ))];
+    for turn in conv.turns.lock().unwrap().iter() {
+        if turn.role == "assistant" {
+            messages.push(ChatMessage::assistant(turn.text.clone()));
         } else {
-            println!("DEBUG: Failed to open registry key for HUGGINGFACE_TOKEN");
+            messages.push(ChatMessage::user(turn.text.clone()));
         }
+    }
+    messages.push(ChatMessage::user(prompt.clone()));
 
-        unsafe {
-            let _ = SendMessageTimeoutW(
-                HWND_BROADCAST,
-                WM_SETTINGCHANGE,
-                WPARAM(0),
-                LPARAM(w!("Environment").as_ptr() as isize),
-                SMTO_ABORTIFHUNG,
-                5000,
-                None,
-            );
+    let client = gemini_client(&cfg)?;
+
+    let res = exec_chat(&cfg, &client, &model, ChatRequest::new(messages), None)
+        .await
+        .map_err(|e| api_error(&client.pin_failure, e))?;
+    remember_response_meta(&conv, &res);
+
+    let answer = res
+        .content_text_as_str()
+        .unwrap_or("[No response]")
+        .to_string();
+    record_exchange(&cfg, &conv, &prompt, &answer);
+    Ok(answer)
+}
+
+#[tauri::command]
+fn get_conversation(conv: tauri::State<'_, Conversation>) -> Vec<ConversationTurn> {
+    conv.turns.lock().unwrap().clone()
+}
+
+#[tauri::command]
+fn clear_conversation(conv: tauri::State<'_, Conversation>) {
+    conv.turns.lock().unwrap().clear();
+}
+
+#[tauri::command]
+fn compact_conversation(conv: tauri::State<'_, Conversation>) -> usize {
+    let mut turns = conv.turns.lock().unwrap();
+    dedupe_turns(&mut turns)
+}
+
+#[tauri::command]
+fn set_conversation_dedupe(enabled: bool, conv: tauri::State<'_, Conversation>) -> bool {
+    conv.dedupe.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+struct Recording {
+    path: PathBuf,
+    file: fs::File,
+    started: Instant,
+    events: usize,
+    last_move: Option<Instant>, // Window drags fire many moves; only a few per second are kept
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct RecordedEvent {
+    t_ms: u64, // Since the recording started
+    kind: String, // "capture", "prompt", "answer" or "window-moved"
+    data: serde_json::Value,
+}
+
+// Replace every secret the app knows (configured keys, tokens, provider keys in the environment)
+// and anything shaped like a Google or Hugging Face key with a placeholder
+fn scrub_secrets(cfg: &AppConfig, text: &str) -> String {
+    static KEY_PATTERN: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let pattern = KEY_PATTERN.get_or_init(|| regex::Regex::new(r"AIza[0-9A-Za-z_\-]{30,}|hf_[A-Za-z0-9]{20,}").unwrap());
+    let mut secrets: Vec<String> = ["GEMINI_API_KEY", "HUGGINGFACE_TOKEN"].iter().filter_map(|name| std::env::var(name).ok()).collect();
+    secrets.extend(cfg.api_key.lock().ok().and_then(|key| key.clone()));
+    secrets.extend(cfg.hf_token.lock().ok().and_then(|token| token.clone()));
+    let mut text = pattern.replace_all(text, "[redacted]").to_string();
+    for secret in secrets.iter().filter(|secret| secret.trim().len() >= 8) {
+        text = text.replace(secret.as_str(), "[redacted]");
+    }
+    text
+}
+
+// Append an event to the active recording, if any; secrets are scrubbed before anything is written
+fn record_event(cfg: &AppConfig, kind: &str, data: serde_json::Value) {
+    use std::io::Write;
+
+    let Ok(mut recording) = cfg.recording.lock() else {
+        return;
+    };
+    let Some(recording) = recording.as_mut() else {
+        return;
+    };
+    if kind == "window-moved" {
+        if recording.last_move.is_some_and(|last| last.elapsed() < Duration::from_millis(250)) {
+            return;
         }
-        println!("DEBUG: Broadcasted environment change");
+        recording.last_move = Some(Instant::now());
     }
-    Ok(())
+    let event = RecordedEvent { t_ms: recording.started.elapsed().as_millis() as u64, kind: kind.to_string(), data };
+    let Ok(line) = serde_json::to_string(&event) else {
+        return;
+    };
+    if writeln!(recording.file, "{}", scrub_secrets(cfg, &line)).is_ok() {
+        recording.events += 1;
+    }
+}
+
+#[derive(serde::Serialize)]
+struct RecordingStatus {
+    path: String,
+    events: usize,
 }
 
+// Start logging captures, prompts, answers and window moves to a new .jsonl file under recordings/
 #[tauri::command]
-fn get_hf_token(cfg: tauri::State<'_, AppConfig>) -> Option<String> {
-    // First try to get from environment variable
-    if let Ok(token) = std::env::var("HUGGINGFACE_TOKEN") {
-        if !token.trim().is_empty() {
-            println!("DEBUG: Found HUGGINGFACE_TOKEN in environment: {}", &token[..std::cmp::min(10, token.len())]);
-            // Update AppConfig to match environment variable
-            if let Ok(mut guard) = cfg.hf_token.lock() {
-                *guard = Some(token.clone());
-            }
-            return Some(token);
-        } else {
-            println!("DEBUG: HUGGINGFACE_TOKEN is empty");
-        }
-    } else {
-        println!("DEBUG: HUGGINGFACE_TOKEN not found in environment");
-    }
-    
-    // If not in environment, try AppConfig
-    if let Ok(guard) = cfg.hf_token.lock() {
-        if let Some(token) = guard.clone() {
-            println!("DEBUG: Found HUGGINGFACE_TOKEN in AppConfig: {}", &token[..std::cmp::min(10, token.len())]);
-            return Some(token);
-        } else {
-            println!("DEBUG: No HUGGINGFACE_TOKEN in AppConfig");
-        }
+fn start_recording(app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<RecordingStatus, String> {
+    let mut recording = cfg.recording.lock().map_err(|_| "Lock poisoned")?;
+    if let Some(active) = recording.as_ref() {
+        return Err(format!("Already recording to {}", active.path.display()));
     }
-    
-    println!("DEBUG: No HUGGINGFACE_TOKEN found anywhere");
-    None
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?.join("recordings");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let path = dir.join(format!("{}.jsonl", unix_now()));
+    let file = fs::File::create(&path).map_err(|e| e.to_string())?;
+    *recording = Some(Recording { path: path.clone(), file, started: Instant::now(), events: 0, last_move: None });
+    Ok(RecordingStatus { path: path.to_string_lossy().to_string(), events: 0 })
 }
 
 #[tauri::command]
-async fn call_gemini(prompt: String, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
-    if std::env::var("GEMINI_API_KEY").is_err() {
-        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+fn stop_recording(cfg: tauri::State<'_, AppConfig>) -> Result<RecordingStatus, String> {
+    let recording = cfg.recording.lock().map_err(|_| "Lock poisoned")?.take().ok_or("Not recording")?;
+    Ok(RecordingStatus { path: recording.path.to_string_lossy().to_string(), events: recording.events })
+}
+
+// Emit a recording's events back as "replay-event" with their original spacing divided by `speed`
+// (default 1.0; gaps are capped at 10 s), then "replay-finished". Returns the number of events.
+#[tauri::command]
+fn replay_session(path: String, speed: Option<f64>, app: tauri::AppHandle, tasks: tauri::State<'_, BackgroundTasks>) -> Result<usize, String> {
+    let speed = speed.unwrap_or(1.0);
+    if !(speed > 0.0 && speed.is_finite()) {
+        return Err("Speed must be a positive number".to_string());
     }
+    let raw = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let events = raw
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| serde_json::from_str::<RecordedEvent>(line).map_err(|e| format!("Line {} of the recording is invalid: {}", i + 1, e)))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let client = Client::default();
+    let count = events.len();
+    let handle = tauri::async_runtime::spawn(async move {
+        let mut previous = 0;
+        for event in events {
+            let gap = Duration::from_millis(event.t_ms.saturating_sub(previous)).div_f64(speed);
+            tokio::time::sleep(gap.min(Duration::from_secs(10))).await;
+            previous = event.t_ms;
+            let _ = app.emit("replay-event", event);
+        }
+        let _ = app.emit("replay-finished", count);
+    });
+    tasks.track("replay", handle);
+    Ok(count)
+}
 
-    let chat_req = ChatRequest::new(vec![
-        ChatMessage::system("Be concise and helpful."),
-        ChatMessage::user(&prompt),
-    ]);
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
 
-    let model = cfg.model.lock().map_err(|_| "Lock poisoned")?.clone();
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct HistoryEntry {
+    id: String,
+    session: String,
+    created_at: u64, // Unix seconds
+    prompt: String,
+    answer: String,
+    model: String,
+    images: Vec<String>, // Copies kept under the history folder
+    #[serde(default)]
+    fingerprint: Option<String>, // SimHash of the problem's OCR text, see fingerprint_capture; empty when it had no text
+}
 
-    let res = client
-        .exec_chat(&model, chat_req, None)
-        .await
-        .map_err(|e| e.to_string())?;
+fn history_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?.join("history");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
 
-    Ok(res
-        .content_text_as_str()
-        .unwrap_or("[No response]")
-        .to_string())
+fn load_history_index(app: &tauri::AppHandle) -> Result<Vec<HistoryEntry>, String> {
+    match fs::read_to_string(history_dir(app)?.join("index.json")) {
+        Ok(raw) => serde_json::from_str(&raw).map_err(|e| format!("History index is corrupt: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
+// Archive the last answered request, copying its source images into the session folder
 #[tauri::command]
-async fn call_gemini_with_image(prompt: String, image_path: String, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
-    if std::env::var("GEMINI_API_KEY").is_err() {
-        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+fn save_to_history(app: tauri::AppHandle, conv: tauri::State<'_, Conversation>) -> Result<HistoryEntry, String> {
+    let request = conv
+        .last_request
+        .lock()
+        .map_err(|_| "Lock poisoned")?
+        .clone()
+        .ok_or("Nothing to save yet; ask a question first")?;
+
+    let id = Uuid::new_v4().to_string();
+    let session_dir = history_dir(&app)?.join(&conv.session_id);
+    fs::create_dir_all(&session_dir).map_err(|e| e.to_string())?;
+
+    // Images already cleared from temp (e.g. by auto-clear) are skipped rather than failing the save
+    let mut images = Vec::new();
+    for (index, source) in request.image_paths.iter().enumerate() {
+        let target = session_dir.join(format!("{}_{}.png", id, index));
+        if fs::copy(source, &target).is_ok() {
+            images.push(target.to_string_lossy().to_string());
+        }
     }
 
-    let mut file = File::open(&image_path).map_err(|e| e.to_string())?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).map_err(|e| e.to_string())?;
+    // Carry over the fingerprint if it was taken of exactly these images
+    let fingerprint = conv
+        .last_fingerprint
+        .lock()
+        .map_err(|_| "Lock poisoned")?
+        .as_ref()
+        .filter(|(paths, _)| *paths == request.image_paths)
+        .map(|(_, fingerprint)| fingerprint.clone());
+    let entry = HistoryEntry {
+        id,
+        session: conv.session_id.clone(),
+        created_at: unix_now(),
+        prompt: request.prompt,
+        answer: request.answer,
+        model: request.model,
+        images,
+        fingerprint,
+    };
+    let mut index = load_history_index(&app)?;
+    index.push(entry.clone());
+    write_history_index(&app, &index)?;
+    Ok(entry)
+}
 
-    let encoded_image = general_purpose::STANDARD.encode(&buffer);
+fn write_history_index(app: &tauri::AppHandle, index: &[HistoryEntry]) -> Result<(), String> {
+    let raw = serde_json::to_string_pretty(index).map_err(|e| e.to_string())?;
+    write_atomic(&history_dir(app)?.join("index.json"), &raw)
+}
 
-    let client = Client::default();
+// FNV-1a, so fingerprints stay comparable across builds (std's hasher makes no such promise)
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
 
-    let chat_req = ChatRequest::new(vec![
-        ChatMessage::system("Be concise and helpful."),
-        ChatMessage::user(vec![
-            ContentPart::from_text(prompt),
-            ContentPart::from_image_base64("image/png", Arc::from(encoded_image)),
-        ]),
-    ]);
+// 64-bit SimHash over word pairs of the normalized text: small OCR differences flip only a few bits.
+// Normalizing drops case, punctuation and one-character tokens, which are mostly OCR noise.
+fn text_fingerprint(text: &str) -> Option<u64> {
+    let normalized = text.to_lowercase().replace(|c: char| !c.is_alphanumeric(), " ");
+    let words: Vec<&str> = normalized.split_whitespace().filter(|word| word.chars().count() > 1).collect();
+    if words.is_empty() {
+        return None;
+    }
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(2.min(words.len())) {
+        let hash = fnv1a(&shingle.join(" "));
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if (hash >> bit) & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    Some(weights.iter().enumerate().filter(|(_, weight)| **weight > 0).fold(0u64, |hash, (bit, _)| hash | (1 << bit)))
+}
 
-    let model = cfg.model.lock().map_err(|_| "Lock poisoned")?.clone();
+// Fingerprints this many bits apart or fewer count as the same problem; a misread word moves a few
+// bits, while unrelated problems differ by around half of the 64
+const FINGERPRINT_MAX_DISTANCE: u32 = 8;
 
-    let res = client
-        .exec_chat(&model, chat_req, None)
-        .await
-        .map_err(|e| e.to_string())?;
+#[derive(serde::Serialize)]
+struct FingerprintCheck {
+    fingerprint: String,
+    seen_before: bool,
+    previous: Option<HistoryEntry>, // Closest earlier answer to this problem
+    distance: Option<u32>, // Differing bits from that answer's fingerprint
+}
 
-    Ok(res
-        .content_text_as_str()
-        .unwrap_or("[No response]")
-        .to_string())
+// Fingerprint the problem on screen (an image, or the whole queue) from its OCR text and look for it in history.
+// Older entries without a fingerprint are fingerprinted from their saved images on first check.
+#[tauri::command]
+async fn fingerprint_capture(
+    path: Option<String>,
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+    conv: tauri::State<'_, Conversation>,
+) -> Result<FingerprintCheck, String> {
+    let image_paths = match path {
+        Some(path) => vec![path],
+        None => queue.images.lock().unwrap().iter().cloned().collect::<Vec<String>>(),
+    };
+    if image_paths.is_empty() {
+        return Err("No images in queue".to_string());
+    }
+    let text = ocr_images_with_progress(&app, &cfg, &image_paths).await?;
+    let hash = text_fingerprint(&text).ok_or("No text found to fingerprint")?;
+    let fingerprint = format!("{:016x}", hash);
+
+    let mut index = load_history_index(&app)?;
+    let mut backfilled = HashMap::new();
+    for entry in index.iter_mut().filter(|entry| entry.fingerprint.is_none() && !entry.images.is_empty()) {
+        let mut old_text = String::new();
+        let mut ocr_failed = false;
+        for image in &entry.images {
+            match ocr_image(&cfg, image).await {
+                Ok(page) => {
+                    old_text.push_str(&page);
+                    old_text.push('\n');
+                }
+                Err(_) => ocr_failed |= std::path::Path::new(image).exists(),
+            }
+        }
+        // An empty fingerprint marks entries with nothing to fingerprint so they aren't OCRed again;
+        // entries whose OCR failed on readable images are retried next time
+        match text_fingerprint(&old_text) {
+            Some(old_hash) => entry.fingerprint = Some(format!("{:016x}", old_hash)),
+            None if !ocr_failed => entry.fingerprint = Some(String::new()),
+            None => continue,
+        }
+        backfilled.insert(entry.id.clone(), entry.fingerprint.clone());
+    }
+    if !backfilled.is_empty() {
+        // The OCR above can take a while; merge into a fresh copy so entries saved meanwhile survive
+        let mut latest = load_history_index(&app)?;
+        for entry in latest.iter_mut().filter(|entry| entry.fingerprint.is_none()) {
+            if let Some(fingerprint) = backfilled.get(&entry.id) {
+                entry.fingerprint = fingerprint.clone();
+            }
+        }
+        write_history_index(&app, &latest)?;
+        index = latest;
+    }
+
+    let closest = index
+        .into_iter()
+        .filter_map(|entry| {
+            let other = u64::from_str_radix(entry.fingerprint.as_deref()?, 16).ok()?;
+            Some(((hash ^ other).count_ones(), entry))
+        })
+        .filter(|(distance, _)| *distance <= FINGERPRINT_MAX_DISTANCE)
+        .min_by_key(|(distance, entry)| (*distance, std::cmp::Reverse(entry.created_at)));
+    *conv.last_fingerprint.lock().map_err(|_| "Lock poisoned")? = Some((image_paths, fingerprint.clone()));
+    Ok(FingerprintCheck {
+        fingerprint,
+        seen_before: closest.is_some(),
+        distance: closest.as_ref().map(|(distance, _)| *distance),
+        previous: closest.map(|(_, entry)| entry),
+    })
 }
 
+// Newest first; an optional query matches prompt or answer case-insensitively
 #[tauri::command]
-fn quit_app(app: tauri::AppHandle) {
-    app.exit(0);
+fn list_history(query: Option<String>, app: tauri::AppHandle) -> Result<Vec<HistoryEntry>, String> {
+    let query = query.map(|q| q.to_lowercase()).filter(|q| !q.trim().is_empty());
+    let mut entries: Vec<HistoryEntry> = load_history_index(&app)?
+        .into_iter()
+        .filter(|entry| match &query {
+            Some(q) => entry.prompt.to_lowercase().contains(q) || entry.answer.to_lowercase().contains(q),
+            None => true,
+        })
+        .collect();
+    entries.reverse();
+    Ok(entries)
 }
 
 #[tauri::command]
-fn add_image_to_queue(queue: tauri::State<'_, ImageQueue>) -> Result<usize, String> {
-    // Debounce: only allow one capture per 500ms
-    {
-        let mut last_capture = queue.last_capture.lock().unwrap();
-        let now = Instant::now();
-        if now.duration_since(*last_capture) < Duration::from_millis(500) {
-            // Return current queue length without adding new image
-            let images = queue.images.lock().unwrap();
-            return Ok(images.len());
+fn open_history_entry(id: String, app: tauri::AppHandle) -> Result<HistoryEntry, String> {
+    load_history_index(&app)?
+        .into_iter()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| format!("No history entry with id '{}'", id))
+}
+
+#[derive(serde::Serialize)]
+struct ReviewCard {
+    id: String,
+    front: String, // The question prompt
+    back: String,  // The saved answer
+    model: String,
+    images: Vec<String>,
+}
+
+// The last `count` saved answers as flashcards, oldest first so they can be stepped through in order
+#[tauri::command]
+fn get_review_cards(count: usize, app: tauri::AppHandle) -> Result<Vec<ReviewCard>, String> {
+    let entries = load_history_index(&app)?;
+    let start = entries.len().saturating_sub(count);
+    Ok(entries
+        .into_iter()
+        .skip(start)
+        .map(|entry| ReviewCard {
+            id: entry.id,
+            front: entry.prompt,
+            back: entry.answer,
+            model: entry.model,
+            images: entry.images,
+        })
+        .collect())
+}
+
+// Fenced code blocks in a markdown answer as (language, code); the language may be empty
+fn code_blocks(text: &str) -> Vec<(String, String)> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match (&mut current, trimmed.strip_prefix("```")) {
+            (None, Some(lang)) => current = Some((lang.trim().to_string(), Vec::new())),
+            (Some(_), Some(_)) => {
+                let (lang, lines) = current.take().unwrap();
+                blocks.push((lang, lines.join("\n")));
+            }
+            (Some((_, lines)), None) => lines.push(line),
+            (None, None) => {}
         }
-        *last_capture = now;
     }
+    blocks
+}
 
-    let screens = Screen::all().map_err(|e| e.to_string())?;
-    let screen = screens.get(0).ok_or("No screens found")?;
+// Monospace font for rendered images: RENDER_FONT overrides the per-OS defaults
+fn load_mono_font() -> Result<ab_glyph::FontVec, String> {
+    let candidates = std::env::var("RENDER_FONT").into_iter().chain(
+        [
+            "C:\\Windows\\Fonts\\consola.ttf",
+            "C:\\Windows\\Fonts\\cour.ttf",
+            "/System/Library/Fonts/Menlo.ttc",
+            "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+            "/usr/share/fonts/TTF/DejaVuSansMono.ttf",
+        ]
+        .map(String::from),
+    );
+    for path in candidates {
+        if let Ok(bytes) = fs::read(&path) {
+            if let Ok(font) = ab_glyph::FontVec::try_from_vec_and_index(bytes, 0) {
+                return Ok(font);
+            }
+        }
+    }
+    Err("No monospace font found; set RENDER_FONT to a .ttf path".to_string())
+}
 
-    let image = screen.capture().map_err(|e| e.to_string())?;
-    let (width, height) = (image.width(), image.height());
+// syntect's bundled syntaxes and themes, deserialized once on first use rather than per render
+fn syntect_defaults() -> (&'static syntect::parsing::SyntaxSet, &'static syntect::highlighting::ThemeSet) {
+    static SYNTAXES: std::sync::OnceLock<syntect::parsing::SyntaxSet> = std::sync::OnceLock::new();
+    static THEMES: std::sync::OnceLock<syntect::highlighting::ThemeSet> = std::sync::OnceLock::new();
+    (
+        SYNTAXES.get_or_init(syntect::parsing::SyntaxSet::load_defaults_newlines),
+        THEMES.get_or_init(syntect::highlighting::ThemeSet::load_defaults),
+    )
+}
 
-    let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, image.into_raw())
-        .ok_or("Failed to convert image")?;
+// Draw highlighted code onto a themed canvas, one monospace cell per character
+fn render_code_image(code: &str, lang: &str, theme_name: &str, font_size: f32) -> Result<image::RgbaImage, String> {
+    use ab_glyph::{Font, ScaleFont};
+    use syntect::easy::HighlightLines;
+    use syntect::util::LinesWithEndings;
 
-    let filename = format!("{}.png", Uuid::new_v4());
-    let mut path = std::env::temp_dir();
-    path.push("tauri_gemini");
-    fs::create_dir_all(&path).ok();
-    path.push(filename);
+    const PADDING: u32 = 24;
 
-    buffer.save(&path).map_err(|e| e.to_string())?;
+    let (syntaxes, themes) = syntect_defaults();
+    let theme = themes.themes.get(theme_name).ok_or_else(|| {
+        format!("Unknown theme '{}'; available: {}", theme_name, themes.themes.keys().cloned().collect::<Vec<_>>().join(", "))
+    })?;
+    let syntax = syntaxes
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
 
-    let mut images = queue.images.lock().unwrap();
-    images.push_back(path.to_string_lossy().to_string());
-    Ok(images.len())
+    let font = load_mono_font()?;
+    let scaled = font.as_scaled(font_size);
+    let advance = scaled.h_advance(font.glyph_id('M'));
+    let line_height = scaled.height() + scaled.line_gap();
+
+    let code = code.replace('\t', "    ");
+    let rows = code.lines().count().max(1) as u32;
+    let cols = code.lines().map(|line| line.chars().count()).max().unwrap_or(0).max(1) as u32;
+    let width = PADDING * 2 + (cols as f32 * advance).ceil() as u32;
+    let height = PADDING * 2 + (rows as f32 * line_height).ceil() as u32;
+
+    let bg = theme.settings.background.unwrap_or(syntect::highlighting::Color::BLACK);
+    let mut canvas = ImageBuffer::from_pixel(width, height, Rgba([bg.r, bg.g, bg.b, 255]));
+    let mut highlighter = HighlightLines::new(syntax, theme);
+
+    for (row, line) in LinesWithEndings::from(&code).enumerate() {
+        let ranges = highlighter.highlight_line(line, syntaxes).map_err(|e| e.to_string())?;
+        let baseline = PADDING as f32 + row as f32 * line_height + scaled.ascent();
+        let mut col = 0;
+        for (style, piece) in ranges {
+            let fg = style.foreground;
+            for c in piece.chars().filter(|c| *c != '\n' && *c != '\r') {
+                let x = PADDING as f32 + col as f32 * advance;
+                col += 1;
+                let glyph = font.glyph_id(c).with_scale_and_position(font_size, ab_glyph::point(x, baseline));
+                let Some(outlined) = font.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    let (px, py) = (bounds.min.x as i32 + gx as i32, bounds.min.y as i32 + gy as i32);
+                    if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+                        return;
+                    }
+                    let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+                    let alpha = coverage.clamp(0.0, 1.0) * fg.a as f32 / 255.0;
+                    for (channel, target) in [fg.r, fg.g, fg.b].into_iter().enumerate() {
+                        pixel[channel] = (pixel[channel] as f32 * (1.0 - alpha) + target as f32 * alpha).round() as u8;
+                    }
+                });
+            }
+        }
+    }
+    Ok(canvas)
 }
 
+const RENDER_FONT_SIZES: std::ops::RangeInclusive<f32> = 8.0..=72.0;
+
+// Font size in pixels for answers rendered to images; high-DPI screens want more than the 16px default
 #[tauri::command]
-fn get_queue_length(queue: tauri::State<'_, ImageQueue>) -> usize {
-    let images = queue.images.lock().unwrap();
-    images.len()
+fn set_render_font_size(px: f32, cfg: tauri::State<'_, AppConfig>) -> Result<f32, String> {
+    if !RENDER_FONT_SIZES.contains(&px) {
+        return Err(format!("Font size must be between {} and {} px", RENDER_FONT_SIZES.start(), RENDER_FONT_SIZES.end()));
+    }
+    *cfg.render_font_size.lock().map_err(|_| "Lock poisoned")? = px;
+    Ok(px)
 }
 
+// Render the last answer's code (or the whole answer if it has no code block) to a PNG at `path`
 #[tauri::command]
-fn clear_queue(queue: tauri::State<'_, ImageQueue>) {
-    let mut images = queue.images.lock().unwrap();
-    images.clear();
+async fn export_answer_image(
+    path: String,
+    theme: Option<String>,
+    cfg: tauri::State<'_, AppConfig>,
+    conv: tauri::State<'_, Conversation>,
+) -> Result<String, String> {
+    let answer = conv
+        .turns
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|turn| turn.role == "assistant")
+        .map(|turn| turn.text.clone())
+        .ok_or("No answer to export yet")?;
+    let (lang, code) = match code_blocks(&answer).as_slice() {
+        [] => (String::new(), answer),
+        blocks => (blocks[0].0.clone(), blocks.iter().map(|(_, code)| code.as_str()).collect::<Vec<_>>().join("\n\n")),
+    };
+    let theme = theme.unwrap_or_else(|| "base16-ocean.dark".to_string());
+    let font_size = *cfg.render_font_size.lock().map_err(|_| "Lock poisoned")?;
+
+    run_image_job(&cfg, move || {
+        render_code_image(&code, &lang, &theme, font_size)?
+            .save(&path)
+            .map_err(|e| e.to_string())?;
+        Ok(path)
+    })
+    .await
 }
 
-#[tauri::command]
-async fn call_gemini_with_image_queue(prompt: String, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
-    if std::env::var("GEMINI_API_KEY").is_err() {
-        return Err("GEMINI_API_KEY environment variable not set.".to_string());
+// Only these link targets survive rendering; anything else (javascript:, data:, file:) becomes "#"
+fn is_safe_link(url: &str) -> bool {
+    let lower = url.trim().to_ascii_lowercase();
+    match lower.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => matches!(scheme, "http" | "https" | "mailto"),
+        _ => true, // Relative links and fragments
     }
+}
 
-    // Collect image paths and release the lock before async operations
-    let image_paths = {
-        let images = queue.images.lock().unwrap();
-        if images.is_empty() {
-            return Err("No images in queue".to_string());
-        }
-        images.iter().cloned().collect::<Vec<String>>()
-    };
+// Convert an answer's markdown to HTML for the overlay. Raw HTML in the answer is escaped rather than
+// passed through, unsafe link schemes are neutralized and fenced code is highlighted with `theme`.
+#[tauri::command]
+fn render_markdown(markdown: String, theme: Option<String>) -> Result<String, String> {
+    use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
 
-    let client = Client::default();
-    let mut content_parts = vec![ContentPart::from_text(prompt)];
+    let theme_name = theme.unwrap_or_else(|| "base16-ocean.dark".to_string());
+    let (syntaxes, themes) = syntect_defaults();
+    let theme = themes.themes.get(&theme_name).ok_or_else(|| {
+        format!("Unknown theme '{}'; available: {}", theme_name, themes.themes.keys().cloned().collect::<Vec<_>>().join(", "))
+    })?;
 
-    // Add all images from the queue
-    for image_path in image_paths.iter() {
-        let mut file = File::open(image_path).map_err(|e| e.to_string())?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).map_err(|e| e.to_string())?;
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut events = Vec::new();
+    let mut code: Option<(String, String)> = None; // (language, text) of the fenced block being read
+    for event in Parser::new_ext(&markdown, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::Text(text) if code.is_some() => code.as_mut().unwrap().1.push_str(&text),
+            Event::End(TagEnd::CodeBlock) => {
+                let (lang, text) = code.take().unwrap_or_default();
+                let syntax = syntaxes
+                    .find_syntax_by_token(&lang)
+                    .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+                let html = syntect::html::highlighted_html_for_string(&text, syntaxes, syntax, theme)
+                    .map_err(|e| e.to_string())?;
+                events.push(Event::Html(CowStr::from(html)));
+            }
+            // Escape instead of rendering any HTML the model wrote
+            Event::Html(html) | Event::InlineHtml(html) => events.push(Event::Text(html)),
+            Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                let dest_url = if is_safe_link(&dest_url) { dest_url } else { CowStr::from("#") };
+                events.push(Event::Start(Tag::Link { link_type, dest_url, title, id }));
+            }
+            Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                let dest_url = if is_safe_link(&dest_url) { dest_url } else { CowStr::from("#") };
+                events.push(Event::Start(Tag::Image { link_type, dest_url, title, id }));
+            }
+            event => events.push(event),
+        }
+    }
 
-        let encoded_image = general_purpose::STANDARD.encode(&buffer);
-        content_parts.push(ContentPart::from_image_base64("image/png", Arc::from(encoded_image)));
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    Ok(html)
+}
+
+#[tauri::command]
+fn define_macro(name: String, steps: Vec<MacroStep>, app: tauri::AppHandle, cfg: tauri::State<'_, AppConfig>) -> Result<(), String> {
+    if steps.is_empty() {
+        return Err("A macro needs at least one step".to_string());
     }
+    let mut persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+    persisted.macros.insert(name, steps);
+    save_persisted_config(&app, &persisted)
+}
 
-    let chat_req = ChatRequest::new(vec![
-        ChatMessage::system("Be concise and helpful. Analyze all provided images in order."),
-        ChatMessage::user(content_parts),
-    ]);
+#[tauri::command]
+fn list_macros(cfg: tauri::State<'_, AppConfig>) -> Result<HashMap<String, Vec<MacroStep>>, String> {
+    Ok(cfg.persisted.lock().map_err(|_| "Lock poisoned")?.macros.clone())
+}
 
-    let model = cfg.model.lock().map_err(|_| "Lock poisoned")?.clone();
+#[derive(serde::Serialize)]
+struct MacroResult {
+    steps_run: usize,
+    answer: Option<String>, // Output of the last ask step, if any
+}
 
-    let res = client
-        .exec_chat(&model, chat_req, None)
-        .await
-        .map_err(|e| e.to_string())?;
+#[tauri::command]
+async fn run_macro(
+    name: String,
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, ImageQueue>,
+    cfg: tauri::State<'_, AppConfig>,
+    conv: tauri::State<'_, Conversation>,
+) -> Result<MacroResult, String> {
+    let steps = cfg
+        .persisted
+        .lock()
+        .map_err(|_| "Lock poisoned")?
+        .macros
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("No macro named '{}'", name))?;
 
-    Ok(res
-        .content_text_as_str()
-        .unwrap_or("[No response]")
-        .to_string())
+    let mut answer = None;
+    for (i, step) in steps.iter().enumerate() {
+        let result = match step {
+            MacroStep::CaptureNamedRegion { name } => capture_named_region(name.clone(), app.clone(), queue.clone(), cfg.clone()).await.map(|_| ()),
+            MacroStep::SetModel { model } => set_model(model.clone(), cfg.clone()).map(|_| ()),
+            MacroStep::CallGeminiSmart { prompt } => call_gemini_smart(prompt.clone(), app.clone(), queue.clone(), cfg.clone(), conv.clone())
+                .await
+                .map(|text| answer = Some(text)),
+        };
+        result.map_err(|e| format!("Macro '{}' failed at step {}: {}", name, i + 1, e))?;
+    }
+
+    Ok(MacroResult { steps_run: steps.len(), answer })
 }
 
 #[tauri::command]
-async fn call_beast_mode(prompt: String, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+async fn call_beast_mode(prompt: String, app: tauri::AppHandle, queue: tauri::State<'_, ImageQueue>, cfg: tauri::State<'_, AppConfig>) -> Result<String, String> {
+    let _slot = request_slot(&cfg)?;
     if std::env::var("GEMINI_API_KEY").is_err() {
         return Err("GEMINI_API_KEY environment variable not set.".to_string());
     }
@@ -524,18 +6781,26 @@ async fn call_beast_mode(prompt: String, queue: tauri::State<'_, ImageQueue>, cf
     };
 
     // Step 1: Use Gemini 2.0 Flash for content extraction (with 1.5 Flash fallback)
-    let client = Client::default();
+    let client = gemini_client(&cfg)?;
     let mut content_parts = vec![ContentPart::from_text(prompt)];
 
-    // Add all images from the queue
+    // Add all readable images from the queue; a single corrupt capture shouldn't sink the run
+    let mut skipped = Vec::new();
     for image_path in image_paths.iter() {
-        let mut file = File::open(image_path).map_err(|e| e.to_string())?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).map_err(|e| e.to_string())?;
-
-        let encoded_image = general_purpose::STANDARD.encode(&buffer);
-        content_parts.push(ContentPart::from_image_base64("image/png", Arc::from(encoded_image)));
+        let path = image_path.clone();
+        match run_image_job(&cfg, move || validate_image(&path).and_then(|_| image_part(&path))).await {
+            Ok(part) => content_parts.push(part),
+            Err(e) => skipped.push(format!("{} ({})", image_name(image_path), e)),
+        }
     }
+    if skipped.len() == image_paths.len() {
+        return Err(format!("All queued images are unreadable:\n- {}", skipped.join("\n- ")));
+    }
+    let skipped_note = if skipped.is_empty() {
+        String::new()
+    } else {
+        format!("> **Note:** Skipped {} unreadable image(s): {}\n\n", skipped.len(), skipped.join(", "))
+    };
 
     let chat_req = ChatRequest::new(vec![
         ChatMessage::system("You are an expert content extractor. Extract ALL text, formulas, diagrams, and structured information from the provided images. Be comprehensive and detailed."),
@@ -543,15 +6808,13 @@ async fn call_beast_mode(prompt: String, queue: tauri::State<'_, ImageQueue>, cf
     ]);
 
     // Use Gemini 2.0 Flash specifically for BEAST MODE extraction
-    let extraction_result = match client
-        .exec_chat("gemini-2.0-flash", chat_req.clone(), None)
+    let extraction_result = match exec_chat(&cfg, &client, "gemini-2.0-flash", chat_req.clone(), None)
         .await
     {
         Ok(result) => result,
         Err(e) => {
             // If gemini-2.0-flash fails, try gemini-1.5-flash as fallback
-            match client
-                .exec_chat("gemini-1.5-flash", chat_req, None)
+            match exec_chat(&cfg, &client, "gemini-1.5-flash", chat_req, None)
                 .await
             {
                 Ok(result) => result,
@@ -570,50 +6833,92 @@ async fn call_beast_mode(prompt: String, queue: tauri::State<'_, ImageQueue>, cf
         .unwrap_or("[No extraction]")
         .to_string();
 
+    // Optionally scrub PII before the text leaves for the (less trusted) HF endpoint
+    let (hf_content, anonymize_note) = if cfg.hf_anonymize.load(Ordering::SeqCst) {
+        let (scrubbed, redactions) = anonymize_text(&extracted_content);
+        (scrubbed, format!("> **Note:** Redacted {} personal detail(s) before sending to Hugging Face.\n\n", redactions))
+    } else {
+        (extracted_content.clone(), String::new())
+    };
+
         // Step 2: Send extracted content to advanced AI model via Hugging Face API
     let hf_token = std::env::var("HUGGINGFACE_TOKEN").ok();
     
     if let Some(token) = hf_token {
-        let http_client = HttpClient::new();
+        let pin_failure = PinFailure::default();
+        let http_client = http_client_builder(&cfg, &pin_failure)?.build().map_err(|e| e.to_string())?;
+        let extra_headers = {
+            let persisted = cfg.persisted.lock().map_err(|_| "Lock poisoned")?;
+            hf_header_map(&persisted.hf_headers)?
+        };
         
         // Prepare the final prompt for advanced AI processing
         let final_prompt = format!(
-            "Based on the extracted content below, provide comprehensive answers:\n\n{}\n\nFor MCQ questions: Identify all possibilities for single correct and multiple correct answers.\nFor coding questions: Provide complete code solutions in the requested language with proper formatting.",
-            extracted_content
+            "{}\n\n{}\n\nFor MCQ questions: Identify all possibilities for single correct and multiple correct answers.\nFor coding questions: Provide complete code solutions in the requested language with proper formatting.",
+            system_prompt(&cfg, BEAST_HF_MODEL, "Based on the extracted content below, provide comprehensive answers:"),
+            hf_content
         );
 
         // Use GPT-OSS-120B via Hugging Face Router API (OpenAI-compatible format)
         let model_endpoint = "https://router.huggingface.co/v1/chat/completions";
         
-        let gpt_response = match http_client
-            .post(model_endpoint)
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", "application/json")
-            .timeout(std::time::Duration::from_secs(120)) // 2 minute timeout
-            .json(&json!({
-                "model": "openai/gpt-oss-120b",
-                "messages": [
-                    {
-                        "role": "user",
-                        "content": final_prompt
-                    }
-                ],
-                "max_tokens": 8192,
-                "temperature": 1.0,
-                "top_p": 0.7
-            }))
-            .send()
-            .await
-        {
-            Ok(response) => {
-                if response.status().is_success() {
-                    match response.json::<serde_json::Value>().await {
+        let payload = json!({
+            "model": BEAST_HF_MODEL,
+            "messages": [
+                {
+                    "role": "user",
+                    "content": final_prompt
+                }
+            ],
+            "max_tokens": 8192,
+            "temperature": 1.0,
+            "top_p": 0.7
+        });
+        // Status and body text, read eagerly so a cold-start 503 can be inspected and retried
+        let send = || async {
+            let response = http_client
+                .post(model_endpoint)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .headers(extra_headers.clone()) // User-configured headers override the defaults above
+                .timeout(std::time::Duration::from_secs(120)) // 2 minute timeout
+                .json(&payload)
+                .send()
+                .await?;
+            let status = response.status();
+            Ok::<_, reqwest::Error>((status, response.text().await))
+        };
+
+        let mut outcome = send().await;
+        // A loading model answers 503 with its estimated_time; wait that out once instead of degrading
+        if cfg.hf_auto_retry.load(Ordering::SeqCst) {
+            if let Ok((status, Ok(body))) = &outcome {
+                if let Some(wait) = hf_cold_start_wait(*status, body) {
+                    let _ = app.emit("beast-waiting", BeastWaiting { estimated_seconds: wait.as_secs_f64() });
+                    tokio::time::sleep(wait).await;
+                    outcome = send().await;
+                }
+            }
+        }
+
+        let gpt_response = match outcome {
+            Ok((status, body)) => {
+                *cfg.last_hf_raw.lock().map_err(|_| "Lock poisoned")? = Some(HfRawResponse {
+                    status: Some(status.as_u16()),
+                    body: body.as_ref().ok().cloned(),
+                    error: body.as_ref().err().map(|e| e.to_string()),
+                    received_at: unix_now(),
+                });
+                let body = body.unwrap_or_default();
+                if status.is_success() {
+                    match serde_json::from_str::<serde_json::Value>(&body) {
                         Ok(json) => {
                             // Handle OpenAI-compatible response format
                             if let Some(choices) = json["choices"].as_array() {
                                 if let Some(first_choice) = choices.first() {
                                     if let Some(message) = first_choice["message"].as_object() {
                                         if let Some(content) = message["content"].as_str() {
+                                            *cfg.last_beast.lock().map_err(|_| "Lock poisoned")? = Some((hf_content.clone(), content.to_string()));
                                             content.to_string()
                                         } else {
                                             "No content in message".to_string()
@@ -631,8 +6936,7 @@ async fn call_beast_mode(prompt: String, queue: tauri::State<'_, ImageQueue>, cf
                         Err(e) => format!("Error parsing model response: {}", e)
                     }
                 } else {
-                    let status = response.status();
-                    let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                    let error_text = if body.is_empty() { "Unknown error".to_string() } else { body };
                     
                     // Handle specific error cases
                     if status == 503 {
@@ -656,6 +6960,13 @@ async fn call_beast_mode(prompt: String, queue: tauri::State<'_, ImageQueue>, cf
                 }
             }
             Err(e) => {
+                let e = api_error(&pin_failure, e);
+                *cfg.last_hf_raw.lock().map_err(|_| "Lock poisoned")? = Some(HfRawResponse {
+                    status: None,
+                    body: None,
+                    error: Some(e.clone()),
+                    received_at: unix_now(),
+                });
                 // Handle network errors gracefully
                 format!(
                     "## BEAST MODE EXTRACTION COMPLETE! 🚀\n\n**Extracted Content:**\n{}\n\n**Note:** Network error occurred while connecting to the Beast Model: {}. The extracted content above contains all the information from your images. You can use this content directly or check your internet connection and try again.",
@@ -664,21 +6975,194 @@ async fn call_beast_mode(prompt: String, queue: tauri::State<'_, ImageQueue>, cf
             }
         };
         
-        Ok(gpt_response)
+        Ok(format!("{}{}{}", skipped_note, anonymize_note, gpt_response))
     } else {
         // Fallback: Return the extracted content with a note
         Ok(format!(
-            "## BEAST MODE EXTRACTION COMPLETE! 🚀\n\n**Extracted Content:**\n{}\n\n**Note:** Hugging Face token not configured. The extracted content above contains all the information from your images. Set a Hugging Face token in the app to enable advanced AI processing.",
-            extracted_content
+            "{}## BEAST MODE EXTRACTION COMPLETE! 🚀\n\n**Extracted Content:**\n{}\n\n**Note:** Hugging Face token not configured. The extracted content above contains all the information from your images. Set a Hugging Face token in the app to enable advanced AI processing.",
+            skipped_note, extracted_content
         ))
     }
 }
 
+#[derive(serde::Serialize)]
+struct CoverageItem {
+    text: String,
+    kind: &'static str, // "question" or "section"
+    matched_terms: usize,
+    total_terms: usize,
+    covered: bool,
+}
+
+#[derive(serde::Serialize)]
+struct CoverageReport {
+    items: Vec<CoverageItem>,
+    covered: usize,
+    possibly_missed: Vec<String>,
+}
+
+// An item counts as answered when this share of its key terms appears in the answer
+const COVERAGE_MIN_SHARE: f32 = 0.4;
+
+const COVERAGE_STOPWORDS: &[&str] = &[
+    "that", "this", "with", "from", "what", "which", "when", "where", "will", "would", "should", "could", "there", "their",
+    "have", "your", "each", "into", "than", "then", "them", "they", "these", "those", "given", "following", "about", "does",
+];
+
+// Lowercase words of four or more letters (and any numbers) that carry an item's meaning
+fn coverage_terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|word| (word.len() >= 4 || word.chars().all(|c| c.is_ascii_digit())) && !word.is_empty())
+        .filter(|word| !COVERAGE_STOPWORDS.contains(&word.as_str()))
+        .collect()
+}
+
+// Split an extraction into questions and sections: headings and numbered or lettered items
+// start a new item; blank lines end one
+fn coverage_items(extracted: &str) -> Vec<String> {
+    static ITEM_START: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let item_start = ITEM_START.get_or_init(|| {
+        regex::Regex::new(r"^\s*(?:#{1,6}\s|(?:Q(?:uestion)?\s*)?\d+[.):]\s|\(?[a-z][).]\s|\*\*(?:Q(?:uestion)?|Part|Problem|Task)\b)")
+            .expect("valid item pattern")
+    });
+    let mut items: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in extracted.lines() {
+        if line.trim().is_empty() || item_start.is_match(line) {
+            if !current.trim().is_empty() {
+                items.push(std::mem::take(&mut current).trim().to_string());
+            }
+            current.clear();
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.trim().is_empty() {
+        items.push(current.trim().to_string());
+    }
+    // Headings alone carry too few terms to judge
+    items.into_iter().filter(|item| coverage_terms(item).len() >= 2).collect()
+}
+
+// Which extracted questions/sections the answer seems to address, by key-term overlap; defaults to
+// the last Beast Mode run. Text matching only: a paraphrased answer can show as missed.
+#[tauri::command]
+fn beast_coverage(extracted: Option<String>, answer: Option<String>, cfg: tauri::State<'_, AppConfig>) -> Result<CoverageReport, String> {
+    let last = cfg.last_beast.lock().map_err(|_| "Lock poisoned")?.clone();
+    let (extracted, answer) = match (extracted, answer, last) {
+        (Some(extracted), Some(answer), _) => (extracted, answer),
+        (extracted, answer, Some((last_extracted, last_answer))) => (extracted.unwrap_or(last_extracted), answer.unwrap_or(last_answer)),
+        _ => return Err("No Beast Mode answer yet; pass the extraction and answer".to_string()),
+    };
+    let answer_terms = coverage_terms(&answer);
+    let items: Vec<CoverageItem> = coverage_items(&extracted)
+        .into_iter()
+        .map(|text| {
+            let terms = coverage_terms(&text);
+            let matched_terms = terms.iter().filter(|term| answer_terms.contains(*term)).count();
+            let covered = matched_terms as f32 >= terms.len() as f32 * COVERAGE_MIN_SHARE;
+            let lead = text.trim_start_matches(['#', '*', ' ']).to_lowercase();
+            let numbered_q = lead.strip_prefix('q').is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()) || rest.starts_with("uestion"));
+            let kind = if text.contains('?') || numbered_q { "question" } else { "section" };
+            CoverageItem { text, kind, matched_terms, total_terms: terms.len(), covered }
+        })
+        .collect();
+    Ok(CoverageReport {
+        covered: items.iter().filter(|item| item.covered).count(),
+        possibly_missed: items.iter().filter(|item| !item.covered).map(|item| item.text.clone()).collect(),
+        items,
+    })
+}
+
+// (pattern, replacement, name) triples for the PII scrub, applied in order. Names are only caught when titled, or
+// labelled and written as a full name ("Candidate: Jane Doe"), so `name: String` style code is left alone.
+// Phone numbers need a country code, an area code in parentheses, or the 555-123-4567 form.
+// The bool marks the name heuristics, which code and sample I/O lines are exempt from.
+fn pii_patterns() -> &'static [(regex::Regex, &'static str, bool)] {
+    static PATTERNS: std::sync::OnceLock<Vec<(regex::Regex, &'static str, bool)>> = std::sync::OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            (r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}", "[EMAIL]", false),
+            (r"\b\d{3}-\d{2}-\d{4}\b", "[ID]", false),
+            (r"\+\d{1,3}[ .-]?(?:\(\d{1,4}\)[ .-]?)?\d{2,4}(?:[ .-]\d{2,4}){1,3}\b|\(\d{3}\)[ .-]?\d{3}[ .-]\d{4}\b|\b\d{3}-\d{3}-\d{4}\b|\b\d{3}\.\d{3}\.\d{4}\b", "[PHONE]", false),
+            (r"(?P<label>(?i:\b(?:name|candidate|interviewer|student|user|author))\s*[:\-])[ \t]*[A-Z][a-z]+(?:[ \t]+[A-Z][a-z]+)+(?P<end>[ \t]*(?:[.,;]|(?m:$)))", "${label} [NAME]${end}", true),
+            (r"\b(?:Mr|Mrs|Ms|Miss|Dr|Prof)\.?\s+[A-Z][a-z]+(?:\s+[A-Z][a-z]+)?", "[NAME]", true),
+        ]
+        .into_iter()
+        .map(|(pattern, replacement, name)| (regex::Regex::new(pattern).expect("valid PII pattern"), replacement, name))
+        .collect()
+    })
+}
+
+// Lines of sample input/output: an Input/Output/Example label, or nothing but numbers and separators
+fn looks_like_io(line: &str) -> bool {
+    static IO_LINE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    IO_LINE
+        .get_or_init(|| regex::Regex::new(r"^\s*(?:(?i:(?:sample\s+)?(?:input|output|example)s?\b).*|[-+]?\d[\d\s.,:;+\-*/()\[\]]*)$").expect("valid I/O pattern"))
+        .is_match(line)
+}
+
+// Redact emails, phone numbers, ID numbers and labelled/titled names; returns the text and redaction count.
+// Contact details are scrubbed everywhere; fenced code blocks and sample I/O lines skip only the name heuristics.
+fn anonymize_text(text: &str) -> (String, usize) {
+    let mut redacted = String::with_capacity(text.len());
+    let mut count = 0;
+    let mut in_fence = false;
+    for line in text.split_inclusive('\n') {
+        let is_fence = line.trim_start().starts_with("```");
+        if is_fence {
+            in_fence = !in_fence;
+        }
+        let code_or_io = is_fence || in_fence || looks_like_io(line.trim_end());
+        let mut scrubbed = line.to_string();
+        for (pattern, replacement, _) in pii_patterns().iter().filter(|(_, _, name)| !(code_or_io && *name)) {
+            count += pattern.find_iter(&scrubbed).count();
+            scrubbed = pattern.replace_all(&scrubbed, *replacement).into_owned();
+        }
+        redacted.push_str(&scrubbed);
+    }
+    (redacted, count)
+}
+
+#[tauri::command]
+fn set_hf_anonymize(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> bool {
+    cfg.hf_anonymize.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
+// Model the Beast pipeline's second stage runs on the Hugging Face router
+const BEAST_HF_MODEL: &str = "openai/gpt-oss-120b";
+
+// Longest cold-start wait honoured before falling back to extraction-only output
+const HF_MAX_COLD_START_WAIT_SECS: f64 = 60.0;
+
+#[derive(Clone, serde::Serialize)]
+struct BeastWaiting {
+    estimated_seconds: f64,
+}
+
+// The capped wait a 503 "model is loading" reply asks for, if it gives an estimated_time
+fn hf_cold_start_wait(status: reqwest::StatusCode, body: &str) -> Option<Duration> {
+    if status != reqwest::StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let estimated = serde_json::from_str::<serde_json::Value>(body).ok()?["estimated_time"].as_f64()?;
+    Some(Duration::from_secs_f64(estimated.clamp(1.0, HF_MAX_COLD_START_WAIT_SECS)))
+}
+
+#[tauri::command]
+fn set_hf_auto_retry(enabled: bool, cfg: tauri::State<'_, AppConfig>) -> bool {
+    cfg.hf_auto_retry.store(enabled, Ordering::SeqCst);
+    enabled
+}
+
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
     tauri::Builder::default()
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
+        .plugin(tauri_plugin_clipboard_manager::init())
         .invoke_handler(tauri::generate_handler![
             move_window,
             nudge_window,
@@ -699,6 +7183,146 @@ async fn main() {
             call_beast_mode,
             set_hf_token,
             get_hf_token,
+            set_ocr_cleanup,
+            clean_ocr_text,
+            call_gemini_with_text,
+            apply_stealth_profile,
+            get_stealth_profile,
+            capture_fraction,
+            call_gemini_smart,
+            set_gemini_endpoint,
+            set_endpoint_allowlist,
+            set_capture_enhancement,
+            call_gemini_follow_up,
+            get_conversation,
+            clear_conversation,
+            compact_conversation,
+            set_conversation_dedupe,
+            capture_text_block,
+            set_hf_headers,
+            get_hf_headers,
+            stop_background_task,
+            stop_all_background_tasks,
+            rerun_last_with_model,
+            capture_work_area,
+            nudge_window_animated,
+            extract_tables,
+            save_named_region,
+            list_named_regions,
+            capture_named_region,
+            define_macro,
+            list_macros,
+            run_macro,
+            set_response_locale,
+            set_answer_length,
+            capture_area_ocr_to_clipboard,
+            focus_mode,
+            check_video_call,
+            set_video_call_guard,
+            confirm_video_call_capture,
+            save_profile,
+            activate_profile,
+            list_profiles,
+            check_credentials,
+            start_credentials_monitor,
+            stop_credentials_monitor,
+            split_capture,
+            set_auto_clear_after_send,
+            save_to_history,
+            list_history,
+            open_history_entry,
+            set_image_threads,
+            stitch_queue,
+            diff_text,
+            set_max_window_size,
+            set_hide_windows_on_capture,
+            get_last_hidden_windows,
+            register_capture_shortcut,
+            suggest_window_size,
+            start_image_server,
+            stop_image_server,
+            create_group,
+            assign_to_group,
+            list_groups,
+            call_gemini_with_group,
+            detect_problem_type,
+            capture_full_screen_bytes,
+            watch_clipboard,
+            set_max_concurrent_requests,
+            export_answer_image,
+            list_monitors,
+            capture_monitor,
+            get_review_cards,
+            set_max_capture_dimension,
+            pin_queue_image,
+            unpin_queue_image,
+            set_answer_template,
+            validate_answer_structure,
+            set_watermark_removal,
+            get_queue_footprint,
+            set_system_prompt_for,
+            call_gemini_stream,
+            set_hf_anonymize,
+            compare_models,
+            set_queue_mode,
+            get_last_response_meta,
+            get_model_capabilities,
+            set_queue_idle_timeout,
+            render_latex,
+            begin_guided_capture,
+            export_queue,
+            import_queue,
+            merge_queue,
+            detect_coding_platform,
+            set_platform_hint,
+            set_meeting_lockdown,
+            get_meeting_lockdown,
+            override_meeting_lockdown,
+            get_scale_factor,
+            append_to_session_image,
+            verify_content_protection,
+            get_last_hf_raw,
+            set_ocr_min_confidence,
+            get_image_routes,
+            confine_to_monitor,
+            set_hf_auto_retry,
+            get_queue_images,
+            ocr_queue,
+            smart_capture,
+            render_markdown,
+            save_session,
+            restore_session,
+            list_sessions,
+            set_blank_frame_retries,
+            set_render_font_size,
+            capture_new_content,
+            set_cert_pins,
+            estimate_tokens,
+            estimate_queue_tokens,
+            set_ocr_preserve_indent,
+            set_adaptive_model,
+            start_recording,
+            stop_recording,
+            replay_session,
+            set_ocr_languages,
+            set_stream_flush_interval,
+            verify_config,
+            capture_area_as_context,
+            clear_screen_context,
+            capture_large_screen,
+            set_temp_policy,
+            get_quota_status,
+            set_quota_limits,
+            annotate_image,
+            set_focus_on_show,
+            purge_temp_before,
+            beast_coverage,
+            toggle_peek_mode,
+            capture_area_checked,
+            set_content_order,
+            set_auto_continue,
+            fingerprint_capture,
+            reset_session_image,
         ])
         .setup(|app| {
             // Initialize and manage app-level toggle state
@@ -706,15 +7330,48 @@ async fn main() {
                 visible: AtomicBool::new(true),
                 last_toggle: Mutex::new(Instant::now() - Duration::from_secs(1)),
                 last_nudge: Mutex::new(Instant::now() - Duration::from_secs(1)),
+                stealth_profile: Mutex::new("interactive".to_string()),
+                max_size: Mutex::new(None),
+                last_position_op: Mutex::new((String::new(), Instant::now())),
+                capture_shortcut: Mutex::new(None),
+                confine_to_monitor: AtomicBool::new(false),
+                focus_on_show: AtomicBool::new(true),
+                peek: AtomicBool::new(false),
             });
             // Initialize image queue
             app.manage(ImageQueue {
                 images: Mutex::new(VecDeque::new()),
                 last_capture: Mutex::new(Instant::now() - Duration::from_secs(1)),
+                captions: Mutex::new(HashMap::new()),
+                window_titles: Mutex::new(HashMap::new()),
+                groups: Mutex::new(HashMap::new()),
+                pinned: Mutex::new(HashSet::new()),
+                auto_clear: AtomicBool::new(false),
+                single_slot: AtomicBool::new(false),
+                last_activity: Mutex::new(Instant::now()),
+                session_image: Mutex::new(None),
+                last_screen: Mutex::new(None),
+            });
+            // Initialize the background task registry
+            app.manage(BackgroundTasks {
+                tasks: Mutex::new(HashMap::new()),
+            });
+            // Initialize conversation history used for follow-ups
+            app.manage(Conversation {
+                turns: Mutex::new(Vec::new()),
+                dedupe: AtomicBool::new(true),
+                last_request: Mutex::new(None),
+                retired_images: Mutex::new(HashSet::new()),
+                last_response_meta: Mutex::new(None),
+                last_fingerprint: Mutex::new(None),
+                session_id: unix_now().to_string(),
             });
             // Initialize runtime configuration
+            let model_from_env = std::env::var("GEMINI_MODEL").is_ok();
             let initial_model = std::env::var("GEMINI_MODEL").unwrap_or_else(|_| "gemini-2.5-pro".to_string());
             let initial_key = std::env::var("GEMINI_API_KEY").ok();
+            // RESPONSE_LOCALE=auto answers in the OS language; unset keeps English
+            let initial_locale = std::env::var("RESPONSE_LOCALE").ok().and_then(|l| resolve_locale(&l));
             let mut initial_hf_token = std::env::var("HUGGINGFACE_TOKEN").ok();
             
             println!("DEBUG: Startup - GEMINI_API_KEY present: {}", initial_key.is_some());
@@ -744,10 +7401,53 @@ async fn main() {
                 }
             }
             
+            let (persisted, config_repairs) = load_persisted_config(app.handle());
+            if !config_repairs.is_empty() {
+                let _ = app.emit("config-repaired", ConfigRepair { reset: config_repairs.clone() });
+            }
             app.manage(AppConfig {
                 api_key: Mutex::new(initial_key),
                 model: Mutex::new(initial_model),
                 hf_token: Mutex::new(initial_hf_token),
+                ocr_cleanup: AtomicBool::new(false),
+                gemini_endpoint: Mutex::new(None),
+                endpoint_allowlist: Mutex::new(vec!["googleapis.com".to_string()]),
+                allow_custom_endpoint: AtomicBool::new(false),
+                capture_enhance: Mutex::new(None),
+                persisted: Mutex::new(persisted),
+                response_locale: Mutex::new(initial_locale),
+                answer_max_words: Mutex::new(None),
+                video_call_guard: AtomicBool::new(false),
+                video_call_confirmed: AtomicBool::new(false),
+                meeting_lockdown: AtomicBool::new(false),
+                meeting_lockdown_override: AtomicBool::new(false),
+                image_pool: Mutex::new(Arc::new(build_image_pool(0)?)),
+                hide_windows_on_capture: AtomicBool::new(false),
+                last_hidden_windows: Mutex::new(Vec::new()),
+                image_server_port: Mutex::new(None),
+                request_slots: Mutex::new((1, Arc::new(tokio::sync::Semaphore::new(1)))),
+                max_capture_dimension: Mutex::new(Some(2048)),
+                strip_watermark: AtomicBool::new(false),
+                hf_anonymize: AtomicBool::new(false),
+                platform_hint: Mutex::new(None),
+                last_hf_raw: Mutex::new(None),
+                last_beast: Mutex::new(None),
+                ocr_min_confidence: Mutex::new(None),
+                image_routes: Mutex::new(HashMap::new()),
+                hf_auto_retry: AtomicBool::new(false),
+                blank_frame_retries: AtomicU32::new(3),
+                ocr_preserve_indent: AtomicBool::new(false),
+                adaptive_threshold: Mutex::new(None),
+                model_explicit: AtomicBool::new(model_from_env),
+                recording: Mutex::new(None),
+                ocr_languages: Mutex::new(Vec::new()),
+                stream_flush_ms: AtomicU32::new(50),
+                config_repairs: Mutex::new(config_repairs),
+                render_font_size: Mutex::new(16.0),
+                screen_context: Mutex::new(None),
+                app: app.handle().clone(),
+                images_first: AtomicBool::new(false),
+                auto_continue: AtomicU32::new(0),
             });
             let window = app.get_webview_window("main").unwrap();
             window.set_always_on_top(true)?;
@@ -755,8 +7455,41 @@ async fn main() {
             window.set_content_protected(true)?;
             window.set_skip_taskbar(true)?;
             // window.set_ignore_cursor_events(true)?;
+            // Come back as the peek strip if the app was closed while peeking
+            if app.state::<AppConfig>().persisted.lock().map(|p| p.peeking).unwrap_or(false) {
+                app.state::<ToggleState>().peek.store(true, Ordering::SeqCst);
+                window.set_size(Size::Logical(LogicalSize { width: PEEK_SIZE.0, height: PEEK_SIZE.1 }))?;
+                start_peek_status(app.handle(), None);
+            }
             Ok(())
         })
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::Moved(position) = event {
+                // AppConfig is managed in setup, which can run after the first events
+                if let (true, Some(cfg)) = (window.label() == "main", window.try_state::<AppConfig>()) {
+                    record_event(&cfg, "window-moved", json!({ "x": position.x, "y": position.y }));
+                }
+            }
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri app");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ocr_cleanup_keeps_numeric_literals() {
+        assert_eq!(cleanup_ocr_text("int mask = 0x1f;"), "int mask = 0x1f;");
+        assert_eq!(cleanup_ocr_text("double big = 1e5;"), "double big = 1e5;");
+        assert_eq!(cleanup_ocr_text("float f = 1f;"), "float f = 1f;");
+    }
+
+    #[test]
+    fn ocr_cleanup_fixes_keywords_in_identifier_position() {
+        assert_eq!(cleanup_ocr_text("1f (x > 0) {"), "if (x > 0) {");
+        assert_eq!(cleanup_ocr_text("n = items.1en();"), "n = items.len();");
+        assert_eq!(cleanup_ocr_text("f0r (i = 0; i < n; i++) {"), "for (i = 0; i < n; i++) {");
+    }
+}
@@ -1,6 +1,7 @@
 import { Button } from "@/components/ui/button";
 import { Textarea } from "@/components/ui/textarea";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { register, unregisterAll } from "@tauri-apps/plugin-global-shortcut";
 import { useEffect, useState, useRef } from "react";
 import ReactMarkdown from "react-markdown";
@@ -45,6 +46,7 @@ const promptTemplates: Record<string, string> = {
 - Then provide the algorithm and explicit Big-O time and space complexity (Best, Average, Worst) in concise bullet points
 - Finally, output exactly one code block with fully working code (include headers)
 `,
+  "auto-detect": `Answer the question(s) shown in the screenshot(s) concisely and accurately.`,
   "beast-mode": `BEAST MODE ACTIVATED! 🚀
 
 Extract ALL content from the provided images and analyze them comprehensively:
@@ -147,15 +149,24 @@ export default function App() {
         });
         setOutput(result);
       } else {
-        // Use regular processing
-        let finalPrompt = language ? `${prompt}\n\nUse the programming language: ${language}.` : prompt;
+        // Use regular processing; auto-detect swaps in the preset matching the captured problem
+        let basePrompt = prompt;
+        let detected = "";
+        if (outputFormat === "auto-detect") {
+          const detection = await invoke<{ problem_type: string; preset: string | null }>("detect_problem_type");
+          detected = detection.problem_type;
+          if (detection.preset) {
+            basePrompt = promptTemplates[detection.preset];
+          }
+        }
+        let finalPrompt = language ? `${basePrompt}\n\nUse the programming language: ${language}.` : basePrompt;
         if (language === "C++") {
           finalPrompt += "\n\nAdditional requirements for C++:\n- Do NOT use any fast I/O boilerplate (e.g., ios::sync_with_stdio(false), cin.tie(nullptr)).\n- Include 'using namespace std;'.";
         }
         const result = await invoke<string>("call_gemini_with_image_queue", {
           prompt: finalPrompt,
         });
-        setOutput(result);
+        setOutput(detected ? `_Detected: ${detected}_\n\n${result}` : result);
       }
     } catch (err) {
       setOutput("Error: " + err);
@@ -166,8 +177,8 @@ export default function App() {
 
   async function handleClearQueue() {
     try {
-      await invoke("clear_queue");
-      setQueueLength(0);
+      const remaining = await invoke<number>("clear_queue");
+      setQueueLength(remaining);
       setOutput("");
       setLoading(false); // Stop any ongoing processing
     } catch (err) {
@@ -201,9 +212,8 @@ export default function App() {
           invoke("nudge_window", { direction: "right", step: 50 });
         });
         await register("CommandOrControl+Shift+Enter", handleSendAllImages);
-        await register("CommandOrControl+Shift+H", () => {
-          handleAddImageToQueue();
-        });
+        // Capture hotkey is handled in Rust so it fires even when the webview lacks focus
+        await invoke("register_capture_shortcut", { accelerator: "CommandOrControl+Shift+H" });
         await register("CommandOrControl+Shift+R", () => {
           handleClearQueue();
         });
@@ -235,8 +245,16 @@ export default function App() {
     }
 
     setupShortcuts();
+    const unlistenCapture = listen<{ queue_length: number | null; error: string | null }>("capture-queued", (event) => {
+      if (event.payload.queue_length !== null) {
+        setQueueLength(event.payload.queue_length);
+      } else {
+        console.error("Error adding image to queue:", event.payload.error);
+      }
+    });
     return () => {
       unregisterAll();
+      unlistenCapture.then((unlisten) => unlisten());
     };
   }, []);
 
@@ -355,6 +373,7 @@ export default function App() {
           <option value="multiple-correct-mcq">multiple-correct-mcq</option>
           <option value="code-without-comments">code-without-comments</option>
           <option value="code-with-explanation">code-with-explanation</option>
+          <option value="auto-detect">auto-detect</option>
           <option value="beast-mode">BEAST MODE!!</option>
         </select>
       </div>